@@ -47,24 +47,26 @@ fn quick_info_display(quick_info: Vec<DisplayEntry>) {
 }
 
 fn main() {
-    let ctrls = NvmeController::get_all().unwrap();
+    let scan = NvmeController::scan();
+    for failure in scan.failures {
+        eprintln!("Failed to query {}: {}", failure.blk_path, failure.error);
+    }
     let mut quick_info: Vec<DisplayEntry> = Vec::new();
-    for c in ctrls {
+    for c in scan.controllers {
         let nss = c.namespaces_get().unwrap();
         for ns in nss {
-            let mut wwid = ns.nguid_get();
-            if wwid.len() == 0 {
-                wwid = ns.eui64_get();
-            }
-            if wwid.len() == 0 {
-                wwid = "0000000000000000";
-            }
+            /* Namespace might only have generic character device */
+            let blk_path = if ns.blk_path_get().is_empty() {
+                ns.generic_path_get()
+            } else {
+                ns.blk_path_get()
+            };
             quick_info.push(DisplayEntry{
-                blk_path:   format!("{}", ns.blk_path_get()),
-                model:      format!("{}", c.mn_get()),
+                blk_path:   blk_path.to_string(),
+                model:      c.mn_get().to_string(),
                 size:       nvme::size_bytes_2_size_human(ns.size_get()),
-                wwid:       wwid.to_string(),
-                firmware:   format!("{}", c.fr_get()),
+                wwid:       ns.wwid(),
+                firmware:   c.fr_get().to_string(),
             });
         }
     }
@@ -22,7 +22,6 @@ extern crate libc;
 use libc::{c_char, c_int};
 use std::ffi::CStr;
 use std::ffi::CString;
-use std::str;
 
 const NVME_OK: c_int = 0;
 const NVME_LIBBUG: c_int = 1;
@@ -31,60 +30,83 @@ pub struct NvmeCtrlC {
     sn:                 *mut c_char,
 }
 
-#[no_mangle]
-pub extern fn nvme_ctrl_get(dev_path: *const c_char,
-                            cnt: *mut *mut NvmeCtrlC,
-                            err_msg: *mut *mut c_char) -> c_int {
-    let mut rc = NVME_OK;
-    assert!(!dev_path.is_null());
-    assert!(!cnt.is_null());
-    assert!(!err_msg.is_null());
+/* Store error message for caller, freed by nvme_err_msg_free() */
+unsafe fn err_msg_set(err_msg: *mut *mut c_char, msg: &str) {
+    let msg = CString::new(msg.replace('\0', "")).unwrap_or_default();
+    *err_msg = msg.into_raw();
+}
 
-    let dev_path = unsafe {
-        CStr::from_ptr(dev_path)
-    }.to_str().unwrap();
+/// # Safety
+///
+/// `dev_path` should be a valid NUL terminated string, `cnt` and
+/// `err_msg` should be valid pointers for writing. Returned `cnt` should
+/// be freed by `nvme_ctrl_free()` and `err_msg` by `nvme_err_msg_free()`.
+#[no_mangle]
+pub unsafe extern "C" fn nvme_ctrl_get(dev_path: *const c_char,
+                                       cnt: *mut *mut NvmeCtrlC,
+                                       err_msg: *mut *mut c_char) -> c_int {
+    if cnt.is_null() || err_msg.is_null() {
+        return NVME_LIBBUG;
+    }
+    *cnt = std::ptr::null_mut();
+    *err_msg = std::ptr::null_mut();
+    if dev_path.is_null() {
+        err_msg_set(err_msg, "Device path is NULL");
+        return NVME_LIBBUG;
+    }
+    let dev_path = match CStr::from_ptr(dev_path).to_str() {
+        Ok(p) => p,
+        Err(e) => {
+            err_msg_set(err_msg, &format!("Invalid device path: {}", e));
+            return NVME_LIBBUG;
+        }
+    };
     let c = match nvme::NvmeController::from_path(dev_path) {
         Ok(c) => c,
         Err(e) => {
-            rc = NVME_LIBBUG;
-            let err_msg_rust = format!("{}", e);
-            unsafe {*err_msg = CString::new(err_msg_rust).unwrap().into_raw();}
-            return rc;
+            err_msg_set(err_msg, &format!("{}", e));
+            return NVME_LIBBUG;
         }
     };
+    /* Serial number is trimmed ASCII, holding no NUL */
     let c = NvmeCtrlC {
-        sn: CString::new(c.sn_get()).unwrap().into_raw(),
+        sn: CString::new(c.sn_get()).unwrap_or_default().into_raw(),
     };
-    unsafe {
-        *cnt = Box::into_raw(Box::new(c));
-    }
-    rc
+    *cnt = Box::into_raw(Box::new(c));
+    NVME_OK
 }
 
+/// # Safety
+///
+/// `cnt` should be NULL or returned by `nvme_ctrl_get()` and not freed
+/// yet.
 #[no_mangle]
-pub extern fn nvme_ctrl_free(cnt: *mut NvmeCtrlC) {
+pub unsafe extern "C" fn nvme_ctrl_free(cnt: *mut NvmeCtrlC) {
     if cnt.is_null() { return }
-    let cnt = unsafe {
-        &mut *cnt
-    };
-    unsafe {
-        CString::from_raw(cnt.sn);
-        Box::from_raw(cnt);
-    }
+    let cnt = Box::from_raw(cnt);
+    drop(CString::from_raw(cnt.sn));
 }
 
+/// # Safety
+///
+/// `cnt` should be NULL or returned by `nvme_ctrl_get()` and not freed
+/// yet. The returned string is valid until `cnt` is freed.
 #[no_mangle]
-pub extern fn nvme_ctrl_sn_get(cnt: *mut NvmeCtrlC) -> *const c_char {
-    let cnt = unsafe {
-        assert!(!cnt.is_null());
-        &mut *cnt
-    };
-    cnt.sn
+pub unsafe extern "C" fn nvme_ctrl_sn_get(cnt: *mut NvmeCtrlC)
+    -> *const c_char {
+    if cnt.is_null() {
+        return std::ptr::null();
+    }
+    (*cnt).sn
 }
 
+/// # Safety
+///
+/// `err_msg` should be NULL or returned by `nvme_ctrl_get()` and not freed
+/// yet.
 #[no_mangle]
-pub extern fn nvme_err_msg_free(err_msg: *mut c_char) {
-    unsafe {
-        libc::free(err_msg as *mut libc::c_void);
-    }
+pub unsafe extern "C" fn nvme_err_msg_free(err_msg: *mut c_char) {
+    if err_msg.is_null() { return }
+    /* Allocated by CString::into_raw(), not by malloc() */
+    drop(CString::from_raw(err_msg));
 }
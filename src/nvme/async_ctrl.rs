@@ -0,0 +1,146 @@
+/*
+ * Copyright (C) 2017 Red Hat, Inc.
+ * This library is free software; you can redistribute it and/or
+ * modify it under the terms of the GNU Lesser General Public
+ * License as published by the Free Software Foundation; either
+ * version 2.1 of the License, or (at your option) any later version.
+ *
+ * This library is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+ * Lesser General Public License for more details.
+ *
+ * You should have received a copy of the GNU Lesser General Public
+ * License along with this library; If not, see <http://www.gnu.org/licenses/>.
+ *
+ * Author: Gris Ge <fge@redhat.com>
+ */
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::cmd_opts::cmd_timeout_scope;
+use super::controller::{NvmeController, NvmeScanResult};
+use super::error::*;
+use super::ioctl::*;
+use super::namespace::NvmeNameSpace;
+use super::root::SystemRoot;
+
+/// Async wrapper of `NvmeController` for tokio runtime. Commands are
+/// issued in the blocking thread pool of tokio via the same transport as
+/// the sync API.
+///
+/// Each command method takes a `timeout`, passed to kernel as timeout of
+/// every NVMe command it issues instead of the one of `NvmeCmdOpts`. When
+/// expired, kernel aborts the command, resetting the controller if abort
+/// fails, and `ErrorKind::TimedOut` or the abort status is returned. None
+/// means timeout of `NvmeCmdOpts`.
+///
+/// Dropping the returned future does not abort anything: it only stops
+/// waiting, commands already submitted to kernel still run to completion
+/// in the blocking thread pool. Only `timeout` bounds how long they run.
+pub struct NvmeAsyncController {
+    ctrl:                       Arc<NvmeController>,
+}
+
+impl NvmeAsyncController {
+    pub fn new(ctrl: NvmeController) -> NvmeAsyncController {
+        NvmeAsyncController {
+            ctrl:               Arc::new(ctrl),
+        }
+    }
+
+    /// The sync controller, its getters of Identify Controller data
+    /// structure do not block.
+    pub fn controller_get(&self) -> &NvmeController {
+        &self.ctrl
+    }
+
+    pub async fn from_path(blk_path: &str) -> Result<NvmeAsyncController> {
+        let blk_path = blk_path.to_string();
+        let ctrl = blocking_run(None, move || {
+            NvmeController::from_path(&blk_path)
+        }).await?;
+        Ok(NvmeAsyncController::new(ctrl))
+    }
+
+    /// Async version of `NvmeController::scan()`.
+    pub async fn scan() -> Result<NvmeScanResult> {
+        NvmeAsyncController::scan_with_root(&SystemRoot::default()).await
+    }
+
+    /// Async version of `NvmeController::scan_with_root()`.
+    pub async fn scan_with_root(root: &SystemRoot) -> Result<NvmeScanResult> {
+        let root = root.clone();
+        blocking_run(None, move || {
+            Ok(NvmeController::scan_with_root(&root))
+        }).await
+    }
+
+    /// Async version of `NvmeController::namespaces_get()`, `timeout`
+    /// applies to each of its commands.
+    pub async fn namespaces_get(&self, timeout: Option<Duration>)
+        -> Result<Vec<NvmeNameSpace>> {
+        self.run(timeout, |ctrl| ctrl.namespaces_get()).await
+    }
+
+    /// Identify command with specified CNS, return 4096 bytes of data.
+    pub async fn identify(&self, cns: u8, nsid: u32, cntid: u16, csi: u8,
+                          timeout: Option<Duration>) -> Result<Vec<u8>> {
+        self.run(timeout, move |ctrl| {
+            let mut data = vec![0u8; NVME_IDENTIFY_DATA_LEN];
+            nvme_ioctl_identify(ctrl.fd_get()?, cns, nsid, cntid, csi,
+                                &mut data)?;
+            Ok(data)
+        }).await
+    }
+
+    /// Async version of `NvmeController::log_page_get()`.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn log_page_get(&self, lid: u8, nsid: u32, lsp: u8, lsi: u16,
+                              csi: u8, rae: bool, offset: u64, len: usize,
+                              timeout: Option<Duration>) -> Result<Vec<u8>> {
+        self.run(timeout, move |ctrl| {
+            ctrl.log_page_get(lid, nsid, lsp, lsi, csi, rae, offset, len)
+        }).await
+    }
+
+    /// Get Features command, return the completion queue entry dword 0.
+    pub async fn feature_get(&self, fid: u8, nsid: u32, cdw11: u32,
+                             timeout: Option<Duration>) -> Result<u32> {
+        self.run(timeout, move |ctrl| {
+            nvme_ioctl_get_feature(ctrl.fd_get()?, fid, nsid, cdw11)
+        }).await
+    }
+
+    /// Set Features command, return the completion queue entry dword 0.
+    pub async fn feature_set(&self, fid: u8, nsid: u32, cdw11: u32,
+                             save: bool, timeout: Option<Duration>)
+        -> Result<u32> {
+        self.run(timeout, move |ctrl| {
+            nvme_ioctl_set_feature(ctrl.fd_get()?, fid, nsid, cdw11, save)
+        }).await
+    }
+
+    async fn run<T, F>(&self, timeout: Option<Duration>, f: F) -> Result<T>
+        where F: FnOnce(&NvmeController) -> Result<T> + Send + 'static,
+              T: Send + 'static {
+        let ctrl = self.ctrl.clone();
+        blocking_run(timeout, move || f(&ctrl)).await
+    }
+}
+
+/* Run `f` in blocking thread pool with `timeout` applied to every NVMe
+ * command it issues. The blocking thread runs nothing else meanwhile, so
+ * the timeout scoped to it only reaches commands of `f`.
+ */
+async fn blocking_run<T, F>(timeout: Option<Duration>, f: F) -> Result<T>
+    where F: FnOnce() -> Result<T> + Send + 'static,
+          T: Send + 'static {
+    match tokio::task::spawn_blocking(
+        move || cmd_timeout_scope(timeout, f)).await {
+        Ok(r) => r,
+        Err(e) => Err(ErrorKind::LibBug(format!(
+            "Blocking task of NVMe command failed: {}", e)).into()),
+    }
+}
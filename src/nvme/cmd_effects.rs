@@ -0,0 +1,234 @@
+/*
+ * Copyright (C) 2017 Red Hat, Inc.
+ * This library is free software; you can redistribute it and/or
+ * modify it under the terms of the GNU Lesser General Public
+ * License as published by the Free Software Foundation; either
+ * version 2.1 of the License, or (at your option) any later version.
+ *
+ * This library is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+ * Lesser General Public License for more details.
+ *
+ * You should have received a copy of the GNU Lesser General Public
+ * License along with this library; If not, see <http://www.gnu.org/licenses/>.
+ *
+ * Author: Gris Ge <fge@redhat.com>
+ */
+
+use std::fmt;
+
+use super::error::*;
+use super::utils::to_u32;
+
+/* Admin and I/O command effects, 256 dwords each, then reserved */
+pub(crate) const NVME_CMD_EFFECTS_LOG_LEN: usize = 4096;
+const NVME_CMD_EFFECTS_OPCODE_COUNT: usize = 256;
+
+const NVME_CMD_EFFECTS_CSUPP: u32 = 1 << 0;
+const NVME_CMD_EFFECTS_LBCC: u32 = 1 << 1;
+const NVME_CMD_EFFECTS_NCC: u32 = 1 << 2;
+const NVME_CMD_EFFECTS_NIC: u32 = 1 << 3;
+const NVME_CMD_EFFECTS_CCC: u32 = 1 << 4;
+const NVME_CMD_EFFECTS_CSE_SHIFT: u32 = 16;
+const NVME_CMD_EFFECTS_CSE_MASK: u32 = 0x7;
+const NVME_CMD_EFFECTS_USS: u32 = 1 << 19;
+
+/// Command Submission and Execution restriction of a command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NvmeCmdSubmitExec {
+    /// No restriction.
+    Unrestricted,
+    /// No other command to the same namespace should be outstanding.
+    SingleNs,
+    /// No other command to any namespace should be outstanding.
+    AllNs,
+    Unknown(u8),
+}
+
+impl NvmeCmdSubmitExec {
+    fn from_raw(cse: u8) -> NvmeCmdSubmitExec {
+        match cse {
+            0 => NvmeCmdSubmitExec::Unrestricted,
+            1 => NvmeCmdSubmitExec::SingleNs,
+            2 => NvmeCmdSubmitExec::AllNs,
+            _ => NvmeCmdSubmitExec::Unknown(cse),
+        }
+    }
+}
+
+impl fmt::Display for NvmeCmdSubmitExec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            NvmeCmdSubmitExec::Unrestricted => write!(f, "unrestricted"),
+            NvmeCmdSubmitExec::SingleNs => write!(f, "single namespace"),
+            NvmeCmdSubmitExec::AllNs => write!(f, "all namespaces"),
+            NvmeCmdSubmitExec::Unknown(c) => write!(f, "unknown(0x{:x})", c),
+        }
+    }
+}
+
+/// Commands Supported and Effects data structure of a single opcode.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct NvmeCmdEffects {
+    raw:                        u32,
+}
+
+impl NvmeCmdEffects {
+    pub fn raw_get(&self) -> u32 { self.raw }
+
+    /// Command Supported (CSUPP).
+    pub fn is_supported(&self) -> bool {
+        self.raw & NVME_CMD_EFFECTS_CSUPP != 0
+    }
+
+    /// Logical Block Content Change (LBCC).
+    pub fn lbcc(&self) -> bool { self.raw & NVME_CMD_EFFECTS_LBCC != 0 }
+
+    /// Namespace Capability Change (NCC), e.g. namespace size changed.
+    pub fn ncc(&self) -> bool { self.raw & NVME_CMD_EFFECTS_NCC != 0 }
+
+    /// Namespace Inventory Change (NIC), e.g. namespace created or
+    /// deleted.
+    pub fn nic(&self) -> bool { self.raw & NVME_CMD_EFFECTS_NIC != 0 }
+
+    /// Controller Capability Change (CCC), e.g. firmware activated.
+    pub fn ccc(&self) -> bool { self.raw & NVME_CMD_EFFECTS_CCC != 0 }
+
+    /// Command Submission and Execution (CSE).
+    pub fn cse(&self) -> NvmeCmdSubmitExec {
+        NvmeCmdSubmitExec::from_raw(
+            ((self.raw >> NVME_CMD_EFFECTS_CSE_SHIFT) &
+             NVME_CMD_EFFECTS_CSE_MASK) as u8)
+    }
+
+    /// UUID Selection Supported (USS), the command accepts UUID index of
+    /// UUID List.
+    pub fn uuid_sel_supported(&self) -> bool {
+        self.raw & NVME_CMD_EFFECTS_USS != 0
+    }
+}
+
+/// Commands Supported and Effects log page (05h).
+#[derive(Debug, Clone)]
+pub struct NvmeCmdEffectsLog {
+    admin:                      Vec<NvmeCmdEffects>,
+    io:                         Vec<NvmeCmdEffects>,
+}
+
+impl NvmeCmdEffectsLog {
+    /// Effects of admin command `opcode`.
+    pub fn admin_effects_get(&self, opcode: u8) -> NvmeCmdEffects {
+        self.admin[opcode as usize]
+    }
+
+    /// Effects of I/O command `opcode` of the command set this log page
+    /// was queried for.
+    pub fn io_effects_get(&self, opcode: u8) -> NvmeCmdEffects {
+        self.io[opcode as usize]
+    }
+
+    /// Supported admin command opcodes.
+    pub fn admin_opcodes_get(&self) -> Vec<u8> {
+        supported_opcodes(&self.admin)
+    }
+
+    /// Supported I/O command opcodes.
+    pub fn io_opcodes_get(&self) -> Vec<u8> {
+        supported_opcodes(&self.io)
+    }
+
+    pub(crate) fn parse(data: &[u8]) -> Result<NvmeCmdEffectsLog> {
+        if data.len() < NVME_CMD_EFFECTS_OPCODE_COUNT * 2 * 4 {
+            return Err(ErrorKind::CorruptedData(
+                "Commands Supported and Effects log page is truncated"
+                .to_string()).into());
+        }
+        let mut effects = data[..NVME_CMD_EFFECTS_OPCODE_COUNT * 2 * 4]
+            .chunks(4)
+            .map(|c| NvmeCmdEffects {
+                raw:            to_u32([c[0], c[1], c[2], c[3]]),
+            })
+            .collect::<Vec<NvmeCmdEffects>>();
+        let io = effects.split_off(NVME_CMD_EFFECTS_OPCODE_COUNT);
+        Ok(NvmeCmdEffectsLog {
+            admin:              effects,
+            io,
+        })
+    }
+}
+
+fn supported_opcodes(effects: &[NvmeCmdEffects]) -> Vec<u8> {
+    effects.iter().enumerate()
+        .filter(|(_, e)| e.is_supported())
+        .map(|(opcode, _)| opcode as u8)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn effects_set(data: &mut [u8], is_admin: bool, opcode: u8, raw: u32) {
+        let mut off = opcode as usize * 4;
+        if !is_admin {
+            off += NVME_CMD_EFFECTS_OPCODE_COUNT * 4;
+        }
+        data[off..off + 4].copy_from_slice(&raw.to_le_bytes());
+    }
+
+    #[test]
+    fn test_parse() {
+        let mut data = vec![0u8; NVME_CMD_EFFECTS_LOG_LEN];
+        effects_set(&mut data, true, 0x06, NVME_CMD_EFFECTS_CSUPP);
+        effects_set(&mut data, true, 0x80,
+                    NVME_CMD_EFFECTS_CSUPP | NVME_CMD_EFFECTS_LBCC |
+                    NVME_CMD_EFFECTS_NCC | NVME_CMD_EFFECTS_NIC |
+                    NVME_CMD_EFFECTS_CCC | 2 << NVME_CMD_EFFECTS_CSE_SHIFT |
+                    NVME_CMD_EFFECTS_USS);
+        effects_set(&mut data, false, 0x01,
+                    NVME_CMD_EFFECTS_CSUPP | NVME_CMD_EFFECTS_LBCC |
+                    1 << NVME_CMD_EFFECTS_CSE_SHIFT);
+        effects_set(&mut data, false, 0xff, 5 << NVME_CMD_EFFECTS_CSE_SHIFT);
+        let log = NvmeCmdEffectsLog::parse(&data).unwrap();
+
+        assert_eq!(log.admin_opcodes_get(), vec![0x06, 0x80]);
+        assert_eq!(log.io_opcodes_get(), vec![0x01]);
+
+        let identify = log.admin_effects_get(0x06);
+        assert!(identify.is_supported());
+        assert!(!identify.lbcc());
+        assert_eq!(identify.cse(), NvmeCmdSubmitExec::Unrestricted);
+
+        let format = log.admin_effects_get(0x80);
+        assert!(format.lbcc() && format.ncc() && format.nic() &&
+                format.ccc() && format.uuid_sel_supported());
+        assert_eq!(format.cse(), NvmeCmdSubmitExec::AllNs);
+
+        let write = log.io_effects_get(0x01);
+        assert!(write.lbcc());
+        assert!(!write.ncc());
+        assert_eq!(write.cse(), NvmeCmdSubmitExec::SingleNs);
+        /* I/O part does not leak into admin part */
+        assert!(!log.admin_effects_get(0x01).is_supported());
+
+        let vendor = log.io_effects_get(0xff);
+        assert!(!vendor.is_supported());
+        assert_eq!(vendor.cse(), NvmeCmdSubmitExec::Unknown(5));
+    }
+
+    #[test]
+    fn test_parse_empty() {
+        let log = NvmeCmdEffectsLog::parse(
+            &vec![0u8; NVME_CMD_EFFECTS_LOG_LEN]).unwrap();
+        assert!(log.admin_opcodes_get().is_empty());
+        assert!(log.io_opcodes_get().is_empty());
+    }
+
+    #[test]
+    fn test_parse_truncated() {
+        for len in &[0, 1024, NVME_CMD_EFFECTS_OPCODE_COUNT * 8 - 1] {
+            assert!(NvmeCmdEffectsLog::parse(&vec![0xffu8; *len]).is_err());
+        }
+    }
+}
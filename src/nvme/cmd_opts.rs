@@ -0,0 +1,285 @@
+/*
+ * Copyright (C) 2017 Red Hat, Inc.
+ * This library is free software; you can redistribute it and/or
+ * modify it under the terms of the GNU Lesser General Public
+ * License as published by the Free Software Foundation; either
+ * version 2.1 of the License, or (at your option) any later version.
+ *
+ * This library is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+ * Lesser General Public License for more details.
+ *
+ * You should have received a copy of the GNU Lesser General Public
+ * License along with this library; If not, see <http://www.gnu.org/licenses/>.
+ *
+ * Author: Gris Ge <fge@redhat.com>
+ */
+
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use super::passthru::NvmeOpcodePolicy;
+use super::utils::mutex_lock;
+
+/* Admin command opcodes */
+const NVME_ADMIN_GET_LOG_PAGE: u8 = 0x02;
+const NVME_ADMIN_IDENTIFY: u8 = 0x06;
+const NVME_ADMIN_SET_FEATURES: u8 = 0x09;
+const NVME_ADMIN_GET_FEATURES: u8 = 0x0a;
+const NVME_ADMIN_FW_COMMIT: u8 = 0x10;
+const NVME_ADMIN_FW_DOWNLOAD: u8 = 0x11;
+const NVME_ADMIN_SELF_TEST: u8 = 0x14;
+const NVME_ADMIN_FORMAT_NVM: u8 = 0x80;
+const NVME_ADMIN_SANITIZE: u8 = 0x84;
+
+/* NVM command set I/O command opcodes */
+const NVME_IO_FLUSH: u8 = 0x00;
+const NVME_IO_READ: u8 = 0x02;
+const NVME_IO_COMPARE: u8 = 0x05;
+
+/* Status field (without phase tag) as linux kernel returns */
+const NVME_STATUS_DNR: u16 = 1 << 14;
+const NVME_STATUS_CRD_SHIFT: u16 = 11;
+const NVME_STATUS_CRD_MASK: u16 = 0x3;
+/* Status Code Type and Status Code */
+const NVME_STATUS_CODE_MASK: u16 = 0x7ff;
+
+/* Transient status codes worth retrying even without CRD set:
+ * Command Interrupted, Namespace Not Ready and Asymmetric Access
+ * Transition.
+ */
+const NVME_TRANSIENT_STATUS: [u16; 3] = [0x021, 0x082, 0x303];
+
+/* Command Retry Delay Time is in units of 100 milliseconds */
+const NVME_CRDT_UNIT_MS: u64 = 100;
+const DEFAULT_BACKOFF_MS: u64 = 100;
+/* Cap exponential backoff at 2^10 times of base delay */
+const MAX_BACKOFF_SHIFT: u32 = 10;
+
+thread_local! {
+    /* Timeout of all commands issued by current thread, see
+     * `cmd_timeout_scope()`.
+     */
+    static CMD_TIMEOUT: Cell<Option<Duration>> = const { Cell::new(None) };
+}
+
+/* Restore previous thread timeout even when command panicked */
+#[cfg(feature = "async")]
+struct CmdTimeoutGuard {
+    prev:                       Option<Duration>,
+}
+
+#[cfg(feature = "async")]
+impl Drop for CmdTimeoutGuard {
+    fn drop(&mut self) {
+        CMD_TIMEOUT.with(|t| t.set(self.prev));
+    }
+}
+
+/* Run `f` with `timeout` overriding timeout of command options for every
+ * command issued by current thread, e.g. by blocking task of async API.
+ * None keeps command options untouched.
+ */
+#[cfg(feature = "async")]
+pub(crate) fn cmd_timeout_scope<T, F>(timeout: Option<Duration>, f: F) -> T
+    where F: FnOnce() -> T {
+    let _guard = CmdTimeoutGuard {
+        prev:                   CMD_TIMEOUT.with(|t| t.get()),
+    };
+    if timeout.is_some() {
+        CMD_TIMEOUT.with(|t| t.set(timeout));
+    }
+    f()
+}
+
+/// Options of issuing a NVMe command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NvmeCmdOpts {
+    /// None means kernel default: 60 seconds for admin commands and 30
+    /// seconds for I/O commands.
+    pub timeout:                Option<Duration>,
+    /// Times to retry when command failed with Do Not Retry (DNR) bit
+    /// cleared in status, and either Command Retry Delay (CRD) set or
+    /// a transient status: Command Interrupted, Namespace Not Ready or
+    /// Asymmetric Access Transition.
+    pub retries:                u32,
+    /// Delay before the first retry, doubled on each later retry. When
+    /// status has Command Retry Delay (CRD) set, the Command Retry Delay
+    /// Time reported by controller is used instead.
+    pub backoff:                Duration,
+}
+
+impl Default for NvmeCmdOpts {
+    /* Unknown commands, e.g. vendor specific, might not be idempotent,
+     * hence no retry.
+     */
+    fn default() -> NvmeCmdOpts {
+        NvmeCmdOpts {
+            timeout:            None,
+            retries:            0,
+            backoff:            Duration::from_millis(DEFAULT_BACKOFF_MS),
+        }
+    }
+}
+
+impl NvmeCmdOpts {
+    fn new(timeout_secs: u64, retries: u32) -> NvmeCmdOpts {
+        NvmeCmdOpts {
+            timeout:            Some(Duration::from_secs(timeout_secs)),
+            retries,
+            ..                  Default::default()
+        }
+    }
+
+    /// Default options of admin command `opcode`: kernel default timeout
+    /// with retries for idempotent queries, Get Log Page, Identify and Get
+    /// Features. Long timeout for Format NVM, Sanitize, Device Self-test
+    /// and firmware commands.
+    pub fn admin_default(opcode: u8) -> NvmeCmdOpts {
+        match opcode {
+            NVME_ADMIN_GET_LOG_PAGE |
+            NVME_ADMIN_IDENTIFY |
+            NVME_ADMIN_GET_FEATURES => NvmeCmdOpts {
+                retries:        3,
+                ..              Default::default()
+            },
+            NVME_ADMIN_SET_FEATURES => NvmeCmdOpts::new(5, 0),
+            NVME_ADMIN_FW_DOWNLOAD => NvmeCmdOpts::new(120, 0),
+            NVME_ADMIN_SELF_TEST => NvmeCmdOpts::new(120, 0),
+            NVME_ADMIN_FW_COMMIT => NvmeCmdOpts::new(600, 0),
+            NVME_ADMIN_SANITIZE => NvmeCmdOpts::new(600, 0),
+            NVME_ADMIN_FORMAT_NVM => NvmeCmdOpts::new(3600, 0),
+            _ => NvmeCmdOpts::default(),
+        }
+    }
+
+    /// Default options of I/O command `opcode`, only idempotent commands
+    /// are retried.
+    pub fn io_default(opcode: u8) -> NvmeCmdOpts {
+        match opcode {
+            NVME_IO_FLUSH | NVME_IO_READ | NVME_IO_COMPARE => NvmeCmdOpts {
+                retries:        3,
+                ..              Default::default()
+            },
+            _ => NvmeCmdOpts::default(),
+        }
+    }
+
+    pub(crate) fn timeout_ms(&self) -> u32 {
+        match self.timeout {
+            Some(t) => {
+                let ms = t.as_millis();
+                if ms > u128::from(u32::MAX) {
+                    u32::MAX
+                } else {
+                    ms as u32
+                }
+            },
+            None => 0,
+        }
+    }
+}
+
+/* Per-opcode option overrides, Command Retry Delay Times and passthrough
+ * opcode policy, shared by controller and its namespaces.
+ */
+#[derive(Default)]
+pub(crate) struct NvmeCmdOptsTable {
+    admin:                      Mutex<HashMap<u8, NvmeCmdOpts>>,
+    io:                         Mutex<HashMap<u8, NvmeCmdOpts>>,
+    crdt:                       Mutex<[u16; 3]>,
+    policy:                     Mutex<NvmeOpcodePolicy>,
+}
+
+impl NvmeCmdOptsTable {
+    pub(crate) fn get(&self, is_admin: bool, opcode: u8) -> NvmeCmdOpts {
+        let mut opts = if is_admin {
+            mutex_lock(&self.admin).get(&opcode).cloned()
+                .unwrap_or_else(|| NvmeCmdOpts::admin_default(opcode))
+        } else {
+            mutex_lock(&self.io).get(&opcode).cloned()
+                .unwrap_or_else(|| NvmeCmdOpts::io_default(opcode))
+        };
+        if let Some(timeout) = CMD_TIMEOUT.with(|t| t.get()) {
+            opts.timeout = Some(timeout);
+        }
+        opts
+    }
+
+    pub(crate) fn set(&self, is_admin: bool, opcode: u8, opts: NvmeCmdOpts) {
+        if is_admin {
+            mutex_lock(&self.admin).insert(opcode, opts);
+        } else {
+            mutex_lock(&self.io).insert(opcode, opts);
+        }
+    }
+
+    pub(crate) fn policy_get(&self) -> NvmeOpcodePolicy {
+        mutex_lock(&self.policy).clone()
+    }
+
+    pub(crate) fn policy_set(&self, policy: NvmeOpcodePolicy) {
+        *mutex_lock(&self.policy) = policy;
+    }
+
+    /* CRDT1, CRDT2 and CRDT3 of Identify Controller data structure */
+    pub(crate) fn crdt_set(&self, crdt: [u16; 3]) {
+        *mutex_lock(&self.crdt) = crdt;
+    }
+
+    /* Delay before retry `attempt` (starting from 0) of command failed with
+     * `status`, None if the command should not be retried.
+     */
+    pub(crate) fn retry_delay(&self, opts: &NvmeCmdOpts, status: u16,
+                              attempt: u32) -> Option<Duration> {
+        if attempt >= opts.retries || status & NVME_STATUS_DNR != 0 {
+            return None;
+        }
+        let crd = (status >> NVME_STATUS_CRD_SHIFT) & NVME_STATUS_CRD_MASK;
+        if crd != 0 {
+            let crdt = mutex_lock(&self.crdt)[crd as usize - 1];
+            if crdt != 0 {
+                return Some(Duration::from_millis(
+                    u64::from(crdt) * NVME_CRDT_UNIT_MS));
+            }
+        } else if !NVME_TRANSIENT_STATUS.contains(
+            &(status & NVME_STATUS_CODE_MASK)) {
+            return None;
+        }
+        Some(opts.backoff * (1u32 << attempt.min(MAX_BACKOFF_SHIFT)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_retry_delay() {
+        let table = NvmeCmdOptsTable::default();
+        table.crdt_set([5, 0, 0]);
+        let opts = NvmeCmdOpts {
+            retries:            2,
+            backoff:            Duration::from_millis(10),
+            ..                  Default::default()
+        };
+        /* Command Interrupted */
+        assert_eq!(table.retry_delay(&opts, 0x021, 0),
+                   Some(Duration::from_millis(10)));
+        assert_eq!(table.retry_delay(&opts, 0x021, 1),
+                   Some(Duration::from_millis(20)));
+        assert_eq!(table.retry_delay(&opts, 0x021, 2), None);
+        /* Asymmetric Access Transition with DNR set */
+        assert_eq!(table.retry_delay(&opts, 0x4303, 0), None);
+        /* Invalid Field in Command is not transient */
+        assert_eq!(table.retry_delay(&opts, 0x002, 0), None);
+        /* Internal Error with CRD 1 and CRD 2 */
+        assert_eq!(table.retry_delay(&opts, 0x0806, 0),
+                   Some(Duration::from_millis(500)));
+        assert_eq!(table.retry_delay(&opts, 0x1006, 0),
+                   Some(Duration::from_millis(10)));
+    }
+}
@@ -0,0 +1,73 @@
+/*
+ * Copyright (C) 2017 Red Hat, Inc.
+ * This library is free software; you can redistribute it and/or
+ * modify it under the terms of the GNU Lesser General Public
+ * License as published by the Free Software Foundation; either
+ * version 2.1 of the License, or (at your option) any later version.
+ *
+ * This library is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+ * Lesser General Public License for more details.
+ *
+ * You should have received a copy of the GNU Lesser General Public
+ * License along with this library; If not, see <http://www.gnu.org/licenses/>.
+ *
+ * Author: Gris Ge <fge@redhat.com>
+ */
+
+use std::fmt;
+
+pub(crate) const NVME_CSI_NVM: u8                   = 0x00;
+pub(crate) const NVME_CSI_KV: u8                    = 0x01;
+pub(crate) const NVME_CSI_ZNS: u8                   = 0x02;
+
+/* Identify I/O Command Set data structure holds 512 command set vectors */
+pub(crate) const NVME_IO_CMD_SET_COMBINATION_COUNT: usize = 512;
+
+/// I/O Command Set Identifier (CSI) as defined by NVMe 2.0.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NvmeCommandSet {
+    Nvm,
+    KeyValue,
+    Zoned,
+    Unknown(u8),
+}
+
+impl NvmeCommandSet {
+    pub fn from_csi(csi: u8) -> NvmeCommandSet {
+        match csi {
+            NVME_CSI_NVM => NvmeCommandSet::Nvm,
+            NVME_CSI_KV => NvmeCommandSet::KeyValue,
+            NVME_CSI_ZNS => NvmeCommandSet::Zoned,
+            _ => NvmeCommandSet::Unknown(csi),
+        }
+    }
+
+    pub fn csi(&self) -> u8 {
+        match *self {
+            NvmeCommandSet::Nvm => NVME_CSI_NVM,
+            NvmeCommandSet::KeyValue => NVME_CSI_KV,
+            NvmeCommandSet::Zoned => NVME_CSI_ZNS,
+            NvmeCommandSet::Unknown(csi) => csi,
+        }
+    }
+
+    /// Whether this command set is enabled in the I/O Command Set vector
+    /// returned by `NvmeController::io_cmd_set_combinations_get()`.
+    pub fn is_in_vector(&self, vector: u64) -> bool {
+        let csi = self.csi();
+        csi < 64 && vector & (1u64 << csi) != 0
+    }
+}
+
+impl fmt::Display for NvmeCommandSet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            NvmeCommandSet::Nvm => write!(f, "NVM"),
+            NvmeCommandSet::KeyValue => write!(f, "Key Value"),
+            NvmeCommandSet::Zoned => write!(f, "Zoned Namespace"),
+            NvmeCommandSet::Unknown(csi) => write!(f, "Unknown(0x{:02x})", csi),
+        }
+    }
+}
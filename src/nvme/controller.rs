@@ -16,22 +16,100 @@
  * Author: Gris Ge <fge@redhat.com>
  */
 
-use ioctl::{nvme_ioctl_admin_cmd, nvme_ioctl_fd_open, NvmeAdminCmd};
+use std::collections::{HashMap, HashSet};
 use std::path::Path;
-use std::fs::read_dir;
+use std::fs::{read_link, File};
+use std::io::Write;
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::io::AsRawFd;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
 use std::mem::size_of;
 use std::str;
 
+use super::cmd_effects::{NvmeCmdEffectsLog, NVME_CMD_EFFECTS_LOG_LEN};
+use super::cmd_opts::NvmeCmdOpts;
+use super::command_set::*;
+use super::ctrl_attr::*;
+use super::discovery::{is_ctrl_name, NvmeCtrlDev, NvmeNsDev,
+                       SYSFS_NVME_CTRL_FOLDER};
 use super::error::*;
+use super::fid_effects::{NvmeFidEffectsLog, NVME_FID_EFFECTS_LOG_LEN};
 use super::ioctl::*;
 use super::spec::{NvmeSpecIdCtrlData};
 use super::namespace::NvmeNameSpace;
-use super::utils::{to_u16, u24_to_u32, to_u32};
+use super::ns_dev::NvmeNsDevPaths;
+use super::multipath::*;
+use super::passthru::*;
+use super::pci::NvmePciInfo;
+use super::persistent_event::*;
+use super::root::SystemRoot;
+use super::subsystem::NvmeSubsystem;
+use super::supported_log::*;
+use super::telemetry::*;
+use super::temperature::*;
+#[cfg(feature = "io_uring")]
+use super::uring::NvmeUringTransport;
+use super::utils::{to_u16, u24_to_u32, to_u32, to_u64,
+                   dir_entry_names_get, mutex_lock, sysfs_read_str};
 
-static SYSFS_NVME_CTRL_FOLDER: &'static str = "/sys/class/nvme/";
+
+/* Maximum worker threads of get_all_parallel() */
+const SCAN_WORKER_MAX: usize = 16;
 
 const NVME_ADMIN_CMD_CNS_ALL_CTRL: u32 = 0x01;
+const NVME_ADMIN_CMD_CNS_CSI_CTRL: u8 = 0x06;
+const NVME_ADMIN_CMD_CNS_IO_CMD_SET: u8 = 0x1c;
+
+const NVME_OACS_NS_MGMT: u16 = 1 << 3;
+const NVME_CMIC_ANA: u8 = 1 << 3;
+
+const NVME_NSID_ALL: u32 = 0xffffffff;
+
+/* Log Page Attributes: extended data (NUMDU and log page offset) */
+const NVME_LPA_EXT_DATA: u8 = 1 << 2;
+/* Persistent Event Log Size of Identify Controller is in 64 KiB units */
+const NVME_PELS_UNIT: u64 = 64 * 1024;
+/* Log Page Attributes: telemetry data area 4 */
+const NVME_LPA_TELEMETRY_DA4: u8 = 1 << 6;
+/* MDTS is in units of minimum memory page size, CAP.MPSMIN is not
+ * accessible via linux kernel, assume 4 KiB.
+ */
+const NVME_MDTS_UNIT: usize = 4096;
+const NVME_MDTS_SHIFT_MAX: u32 = 19;
+/* MDTS 0 means no limit from controller, but linux kernel still limits
+ * the transfer size of passthrough command.
+ */
+const NVME_LOG_XFER_LEN_DEFAULT: usize = 1 << 20;
+/* Without extended data, only the 16 bits NUMDL field is available */
+const NVME_LOG_NUMDL_MAX_LEN: usize = (1 << 16) * 4;
 
+const NVME_FEAT_HOST_BEHAVIOR: u8 = 0x16;
+const NVME_FEAT_HOST_BEHAVIOR_LEN: usize = 512;
+/* Extended Telemetry Data Area 4 Supported byte of Host Behavior Support */
+const NVME_FEAT_HOST_BEHAVIOR_ETDAS: usize = 1;
+const NVME_FEAT_IO_CMD_SET_PROFILE: u8 = 0x19;
+const NVME_FEAT_IO_CMD_SET_PROFILE_MASK: u32 = 0x1ff;
+
+/// Failure of a single controller during enumeration.
+#[derive(Debug)]
+pub struct NvmeScanFailure {
+    /// Device path of the failed controller, e.g. `/dev/nvme0`.
+    pub blk_path:               String,
+    pub error:                  NvmeError,
+}
+
+/// Result of enumeration which tolerates per-controller failures.
+#[derive(Default)]
+pub struct NvmeScanResult {
+    pub controllers:            Vec<NvmeController>,
+    pub failures:               Vec<NvmeScanFailure>,
+}
+
+/// NVMe controller. It is `Send + Sync`, commands could be issued from
+/// multiple threads on the same controller or its namespaces. Command
+/// sequences which require multiple commands are serialized per file
+/// descriptor.
 pub struct NvmeController {
     raw_id_data:                NvmeSpecIdCtrlData,
     // Converting u8 array to utf8 might have error which we don't want to
@@ -44,10 +122,23 @@ pub struct NvmeController {
     subnqn:                     String,
     ver_str:                    String,
     blk_path:                   String,
+    name:                       String,
+    // Shared with namespaces created from this controller, None after
+    // close().
+    fd:                         Option<Arc<NvmeDevFd>>,
+    root:                       SystemRoot,
+    /* Commands Supported and Effects log page of NVM command set */
+    cmd_effects:                Mutex<Option<NvmeCmdEffectsLog>>,
+    supported_logs:             Mutex<Option<NvmeSupportedLogPages>>,
 }
 
 impl NvmeController {
     pub fn blk_path_get(&self)  -> &str { &self.blk_path }
+    pub fn root_get(&self)      -> &SystemRoot { &self.root }
+
+    /// Kernel name of this controller, e.g. `nvme0`, even if it was
+    /// opened via a renamed device node or symbolic link.
+    pub fn name_get(&self) -> &str { &self.name }
     pub fn vid_get(&self)       -> u16  { to_u16(self.raw_id_data.vid) }
     pub fn ssvid_get(&self)     -> u16  { to_u16(self.raw_id_data.ssvid) }
     pub fn sn_get(&self)        -> &str { &self.sn }
@@ -89,6 +180,15 @@ impl NvmeController {
     pub fn mntmt_get(&self)     -> u16 { to_u16(self.raw_id_data.mntmt) }
     pub fn mxtmt_get(&self)     -> u16 { to_u16(self.raw_id_data.mxtmt) }
     pub fn sanicap_get(&self)   -> u32 { to_u32(self.raw_id_data.sanicap) }
+    pub fn hmminds_get(&self)   -> u32 { to_u32(self.raw_id_data.hmminds) }
+    pub fn hmmaxd_get(&self)    -> u16 { to_u16(self.raw_id_data.hmmaxd) }
+    pub fn nsetidmax_get(&self) -> u16 { to_u16(self.raw_id_data.nsetidmax) }
+    pub fn endgidmax_get(&self) -> u16 { to_u16(self.raw_id_data.endgidmax) }
+    pub fn anatt_get(&self)     -> u8 { self.raw_id_data.anatt }
+    pub fn anacap_get(&self)    -> u8 { self.raw_id_data.anacap }
+    pub fn anagrpmax_get(&self) -> u32 { to_u32(self.raw_id_data.anagrpmax) }
+    pub fn nanagrpid_get(&self) -> u32 { to_u32(self.raw_id_data.nanagrpid) }
+    pub fn pels_get(&self)      -> u32 { to_u32(self.raw_id_data.pels) }
     pub fn sqes_get(&self)      -> u8 { self.raw_id_data.sqes }
     pub fn cqes_get(&self)      -> u8 { self.raw_id_data.cqes }
     pub fn maxcmd_get(&self)    -> u16 { to_u16(self.raw_id_data.maxcmd) }
@@ -102,6 +202,7 @@ impl NvmeController {
     pub fn nvscc_get(&self)     -> u8 { self.raw_id_data.nvscc }
     pub fn acwu_get(&self)      -> u16 { to_u16(self.raw_id_data.acwu) }
     pub fn sgls_get(&self)      -> u32 { to_u32(self.raw_id_data.sgls) }
+    pub fn mnan_get(&self)      -> u32 { to_u32(self.raw_id_data.mnan) }
     pub fn subnqn_get(&self)    -> &str { &self.subnqn }
     pub fn ioccsz_get(&self)    -> u32 { to_u32(self.raw_id_data.ioccsz) }
     pub fn iorcsz_get(&self)    -> u32 { to_u32(self.raw_id_data.iorcsz) }
@@ -109,29 +210,895 @@ impl NvmeController {
     pub fn ctrattr_get(&self)   -> u8 { self.raw_id_data.ctrattr }
     pub fn msdbd_get(&self)     -> u8 { self.raw_id_data.msdbd }
 
+    /// Warning composite temperature threshold in Celsius, None if not
+    /// reported by controller.
+    pub fn wctemp_celsius_get(&self) -> Option<f64> {
+        match self.wctemp_get() {
+            0 => None,
+            k => Some(kelvin_to_celsius(k)),
+        }
+    }
+
+    /// Critical composite temperature threshold in Celsius, None if not
+    /// reported by controller.
+    pub fn cctemp_celsius_get(&self) -> Option<f64> {
+        match self.cctemp_get() {
+            0 => None,
+            k => Some(kelvin_to_celsius(k)),
+        }
+    }
+
     pub fn ver_gen(major: u16, minor: u8, tertiary: u8) -> u32 {
-        ((major as u32) << 16 + (minor as u32) << 8 + tertiary) as u32
+        (u32::from(major) << 16) | (u32::from(minor) << 8) |
+            u32::from(tertiary)
     }
 
     pub fn ver_str_get(&self)   -> &str { &self.ver_str }
 
+    /* Linux kernel use this prefix with NSID as namespace WWID when no
+     * UUID, NGUID or EUI64 is provided. Trailing spaces and NULL of SN and
+     * MN are stripped.
+     */
+    pub(crate) fn wwid_prefix_get(&self) -> String {
+        fn hex_trimmed(data: &[u8]) -> String {
+            let len = data.iter().rposition(|c| *c != b' ' && *c != 0)
+                .map_or(0, |i| i + 1);
+            data[..len].iter().map(|c| format!("{:02x}", c)).collect()
+        }
+        format!("nvme.{:04x}-{}-{}", self.vid_get(),
+                hex_trimmed(&self.raw_id_data.sn),
+                hex_trimmed(&self.raw_id_data.mn))
+    }
+
+    /// Whether the controller supports Asymmetric Namespace Access
+    /// reporting.
+    pub fn ana_supported(&self) -> bool {
+        self.cmic_get() & NVME_CMIC_ANA != 0
+    }
+
+    /// Query the Asymmetric Namespace Access log page (0Ch) including
+    /// NSID list of each ANA group.
+    pub fn ana_log_get(&self) -> Result<NvmeAnaLog> {
+        if !self.ana_supported() {
+            return Err(ErrorKind::NotSupported(format!(
+                "Controller {} does not support ANA reporting",
+                self.blk_path_get())).into());
+        }
+        /* Every namespace belongs to one ANA group, MNAN 0 means the
+         * maximum number of namespaces is NN.
+         */
+        let max_ns = match self.mnan_get() {
+            0 => self.nn_get(),
+            mnan => mnan,
+        };
+        let len = NVME_ANA_LOG_HEADER_LEN +
+            self.anagrpmax_get() as usize * NVME_ANA_GROUP_DESC_LEN +
+            max_ns as usize * 4;
+        /* Linux kernel reads this log on ANA change event, retain the
+         * event for it.
+         */
+        let data = self.log_page_read(&NvmeGetLogArgs {
+            lid:                NVME_LOG_ANA,
+            rae:                true,
+            ..                  Default::default()
+        }, 0, len)?;
+        NvmeAnaLog::parse(&data)
+    }
+
+    /// Get Log Page command of `len` bytes starting at byte `offset`.
+    /// The transfer is split into several commands by MDTS, Retain
+    /// Asynchronous Event is set on all but the last command unless `rae`
+    /// is true. Non-zero `offset` or split transfer requires extended data
+    /// support indicated by bit 2 of `lpa_get()`. Command with non-zero
+    /// `lsp` is never retried.
+    #[allow(clippy::too_many_arguments)]
+    pub fn log_page_get(&self, lid: u8, nsid: u32, lsp: u8, lsi: u16,
+                        csi: u8, rae: bool, offset: u64, len: usize)
+        -> Result<Vec<u8>> {
+        self.log_page_read(&NvmeGetLogArgs {
+            lid,
+            nsid,
+            lsp,
+            lsi,
+            csi,
+            rae,
+            /* Log specific parameter might have side effect, e.g.
+             * telemetry Create bit, hence no retry.
+             */
+            no_retry:           lsp != 0,
+        }, offset, len)
+    }
+
+    pub(crate) fn log_page_read(&self, args: &NvmeGetLogArgs, offset: u64,
+                                len: usize) -> Result<Vec<u8>> {
+        let fd = self.fd_get()?;
+        let mut data = Vec::with_capacity(len);
+        /* Keep other command sequences from interleaving with the log page
+         * reading on this file descriptor.
+         */
+        let _lock = fd.lock();
+        self.log_page_stream(fd, args, offset, len, &mut |piece| {
+            data.extend_from_slice(piece);
+            Ok(())
+        })?;
+        Ok(data)
+    }
+
+    /* Read log page in MDTS sized commands, pass each piece of data to
+     * `out`. Caller should hold the command sequence lock of `fd`.
+     */
+    fn log_page_stream(&self, fd: &NvmeDevFd, args: &NvmeGetLogArgs,
+                       offset: u64, len: usize,
+                       out: &mut dyn FnMut(&[u8]) -> Result<()>)
+        -> Result<()> {
+        if len == 0 {
+            return Err(ErrorKind::InvalidArgument(
+                "Log page length should not be zero".to_string()).into());
+        }
+        if !offset.is_multiple_of(4) {
+            return Err(ErrorKind::InvalidArgument(format!(
+                "Log page offset {} is not dword aligned", offset)).into());
+        }
+        /* Log page is transferred in dwords */
+        let buf_len = len.div_ceil(4) * 4;
+        if offset.checked_add(buf_len as u64).is_none() {
+            return Err(ErrorKind::InvalidArgument(format!(
+                "Log page offset {} with length {} overflows", offset, len))
+                .into());
+        }
+        let mut chunk_len = match self.mdts_get() {
+            0 => NVME_LOG_XFER_LEN_DEFAULT,
+            mdts => NVME_MDTS_UNIT <<
+                u32::from(mdts).min(NVME_MDTS_SHIFT_MAX),
+        };
+        if self.lpa_get() & NVME_LPA_EXT_DATA == 0 {
+            chunk_len = chunk_len.min(NVME_LOG_NUMDL_MAX_LEN);
+            if offset != 0 || buf_len > chunk_len {
+                return Err(ErrorKind::NotSupported(format!(
+                    "Controller {} does not support extended data for Get \
+                     Log Page, log page offset {} or length over {} bytes is \
+                     not supported", self.name_get(), offset, chunk_len))
+                    .into());
+            }
+        }
+
+        let mut buf = vec![0u8; chunk_len.min(buf_len)];
+        let mut done = 0;
+        while done < buf_len {
+            let piece_len = (buf_len - done).min(chunk_len);
+            let piece = &mut buf[..piece_len];
+            let piece_args = NvmeGetLogArgs {
+                rae:            args.rae || done + piece_len < buf_len,
+                ..              *args
+            };
+            nvme_ioctl_get_log(fd, &piece_args, offset + done as u64, piece)?;
+            out(&piece[..piece_len.min(len - done)])?;
+            done += piece_len;
+        }
+        Ok(())
+    }
+
+    /// Capture telemetry log page up to the end of data area `data_area`
+    /// (1 to 4) and write it including the header to `writer`. For
+    /// host-initiated telemetry, controller captures new data before
+    /// reading. Data area 4 requires extended telemetry data area 4
+    /// supported by controller and enabled via Host Behavior Support
+    /// feature.
+    pub fn telemetry_capture(&self, kind: NvmeTelemetryType, data_area: u8,
+                             writer: &mut dyn Write)
+        -> Result<NvmeTelemetryHeader> {
+        if data_area == 0 || data_area > NVME_TELEMETRY_DATA_AREA_MAX {
+            return Err(ErrorKind::InvalidArgument(format!(
+                "Invalid telemetry data area {}, should be 1 to {}",
+                data_area, NVME_TELEMETRY_DATA_AREA_MAX)).into());
+        }
+        if self.is_log_supported(kind.lid())? == Some(false) {
+            return Err(ErrorKind::NotSupported(format!(
+                "Controller {} does not support {} telemetry log page",
+                self.name_get(), kind)).into());
+        }
+        if data_area == NVME_TELEMETRY_DATA_AREA_MAX &&
+           !self.telemetry_da4_enabled() {
+            return Err(ErrorKind::NotSupported(format!(
+                "Telemetry data area 4 is not supported or not enabled on \
+                 controller {}", self.name_get())).into());
+        }
+
+        let fd = self.fd_get()?;
+        let _lock = fd.lock();
+        let data = self.telemetry_header_read(fd, kind, true)?;
+        let header = NvmeTelemetryHeader::parse(&data, kind)?;
+        writer.write_all(&data)?;
+        let len = header.len_get(data_area);
+        if len > NVME_TELEMETRY_BLOCK_LEN {
+            /* Controller-initiated telemetry log page is checked again for
+             * generation number afterwards, the asynchronous event is
+             * cleared by then.
+             */
+            self.log_page_stream(fd, &NvmeGetLogArgs {
+                lid:            kind.lid(),
+                rae:            kind == NvmeTelemetryType::Controller,
+                ..              Default::default()
+            }, NVME_TELEMETRY_BLOCK_LEN as u64, len - NVME_TELEMETRY_BLOCK_LEN,
+            &mut |piece| Ok(writer.write_all(piece)?))?;
+        }
+        if kind == NvmeTelemetryType::Controller {
+            let new_header = NvmeTelemetryHeader::parse(
+                &self.telemetry_header_read(fd, kind, false)?, kind)?;
+            if new_header.gen_get(kind) != header.gen_get(kind) {
+                return Err(ErrorKind::CorruptedData(format!(
+                    "Controller-initiated telemetry data of controller {} \
+                     changed during capture, generation number {} to {}",
+                    self.name_get(), header.gen_get(kind),
+                    new_header.gen_get(kind))).into());
+            }
+        }
+        Ok(header)
+    }
+
+    /* Read telemetry log page header. Host-initiated telemetry data is
+     * created by the first read.
+     */
+    fn telemetry_header_read(&self, fd: &NvmeDevFd, kind: NvmeTelemetryType,
+                             first: bool) -> Result<Vec<u8>> {
+        let lsp = if first && kind == NvmeTelemetryType::Host {
+            NVME_TELEMETRY_LSP_CREATE
+        } else {
+            0
+        };
+        let mut data = Vec::with_capacity(NVME_TELEMETRY_BLOCK_LEN);
+        self.log_page_stream(fd, &NvmeGetLogArgs {
+            lid:                kind.lid(),
+            lsp,
+            rae:                first,
+            no_retry:           lsp == NVME_TELEMETRY_LSP_CREATE,
+            ..                  Default::default()
+        }, 0, NVME_TELEMETRY_BLOCK_LEN, &mut |piece| {
+            data.extend_from_slice(piece);
+            Ok(())
+        })?;
+        Ok(data)
+    }
+
+    /* Whether telemetry data area 4 is supported and enabled by Extended
+     * Telemetry Data Area 4 Supported of Host Behavior Support feature.
+     */
+    fn telemetry_da4_enabled(&self) -> bool {
+        if self.lpa_get() & NVME_LPA_TELEMETRY_DA4 == 0 {
+            return false;
+        }
+        let fd = match self.fd_get() {
+            Ok(fd) => fd,
+            Err(_) => return false,
+        };
+        let mut data = vec![0u8; NVME_FEAT_HOST_BEHAVIOR_LEN];
+        match nvme_ioctl_get_feature_data(fd, NVME_FEAT_HOST_BEHAVIOR, 0, 0,
+                                          &mut data) {
+            Ok(_) => data[NVME_FEAT_HOST_BEHAVIOR_ETDAS] & 1 != 0,
+            Err(_) => false,
+        }
+    }
+
+    /// Read the Persistent Event log page (0Dh). The reporting context is
+    /// established, the whole log page is read, then the context is
+    /// released.
+    pub fn persistent_event_log_get(&self)
+        -> Result<NvmePersistentEventLog> {
+        if self.is_log_supported(NVME_LOG_PERSISTENT_EVENT)? == Some(false) {
+            return Err(ErrorKind::NotSupported(format!(
+                "Controller {} does not support Persistent Event log page",
+                self.name_get())).into());
+        }
+        let fd = self.fd_get()?;
+        let _lock = fd.lock();
+        let ret = self.persistent_event_log_read(fd);
+        /* Always release the context, error of reading takes precedence */
+        let release = self.persistent_event_action(
+            fd, NVME_PEL_ACTION_RELEASE, 0, NVME_PEL_HEADER_LEN,
+            &mut |_| Ok(()));
+        let data = ret?;
+        release?;
+        NvmePersistentEventLog::parse(&data)
+    }
+
+    /// Release the Persistent Event log page reporting context, e.g. the
+    /// one left by an interrupted reader.
+    pub fn persistent_event_context_release(&self) -> Result<()> {
+        let fd = self.fd_get()?;
+        let _lock = fd.lock();
+        self.persistent_event_action(fd, NVME_PEL_ACTION_RELEASE, 0,
+                                     NVME_PEL_HEADER_LEN, &mut |_| Ok(()))
+    }
+
+    /* Establish reporting context with header read, then read events */
+    fn persistent_event_log_read(&self, fd: &NvmeDevFd) -> Result<Vec<u8>> {
+        let mut data = Vec::with_capacity(NVME_PEL_HEADER_LEN);
+        self.persistent_event_action(fd, NVME_PEL_ACTION_ESTABLISH, 0,
+                                     NVME_PEL_HEADER_LEN, &mut |piece| {
+            data.extend_from_slice(piece);
+            Ok(())
+        })?;
+        let tll = NvmePersistentEventHeader::parse(&data)?.tll;
+        if tll < NVME_PEL_HEADER_LEN as u64 || tll > usize::MAX as u64 {
+            return Err(ErrorKind::CorruptedData(format!(
+                "Invalid Persistent Event log page length {}", tll)).into());
+        }
+        /* PELS 0 means the controller does not report the maximum size */
+        let pels = u64::from(self.pels_get()) * NVME_PELS_UNIT;
+        if pels != 0 && tll > pels {
+            return Err(ErrorKind::CorruptedData(format!(
+                "Persistent Event log page length {} exceeds maximum size {} \
+                 reported by controller", tll, pels)).into());
+        }
+        if tll as usize > NVME_PEL_HEADER_LEN {
+            /* Log page offset requires extended data, read the whole log
+             * page again from the start otherwise.
+             */
+            let offset = if self.lpa_get() & NVME_LPA_EXT_DATA == 0 {
+                data.clear();
+                0
+            } else {
+                NVME_PEL_HEADER_LEN
+            };
+            self.persistent_event_action(fd, NVME_PEL_ACTION_READ,
+                                         offset as u64, tll as usize - offset,
+                                         &mut |piece| {
+                data.extend_from_slice(piece);
+                Ok(())
+            })?;
+        }
+        Ok(data)
+    }
+
+    fn persistent_event_action(&self, fd: &NvmeDevFd, action: u8,
+                               offset: u64, len: usize,
+                               out: &mut dyn FnMut(&[u8]) -> Result<()>)
+        -> Result<()> {
+        self.log_page_stream(fd, &NvmeGetLogArgs {
+            lid:                NVME_LOG_PERSISTENT_EVENT,
+            lsp:                action,
+            /* Establishing context twice would lose the first snapshot */
+            no_retry:           action != NVME_PEL_ACTION_READ,
+            ..                  Default::default()
+        }, offset, len, out)
+    }
+
+    /* Read /sys/class/nvme/nvmeX/<attr_name> */
+    fn sysfs_attr_get(&self, attr_name: &str) -> Result<String> {
+        sysfs_read_str(&format!("{}/{}",
+                                self.root.ctrl_sysfs_path(self.name_get()),
+                                attr_name))
+    }
+
+    fn sysfs_attr_parse<T: str::FromStr>(&self, attr_name: &str)
+        -> Result<T> {
+        let value = self.sysfs_attr_get(attr_name)?;
+        value.parse::<T>().map_err(|_| ErrorKind::CorruptedData(format!(
+            "Invalid value '{}' of sysfs attribute {} of controller {}",
+            value, attr_name, self.name_get())).into())
+    }
+
+    /// Transport of this controller.
+    pub fn transport_get(&self) -> Result<NvmeTransport> {
+        Ok(NvmeTransport::from_sysfs(&self.sysfs_attr_get("transport")?))
+    }
+
+    /// Address of this controller as linux kernel shows, PCI BDF for PCIe
+    /// controller, e.g. `0000:01:00.0`, `traddr=<addr>,trsvcid=<port>`
+    /// with optional `host_traddr` and `src_addr` for fabrics controller.
+    pub fn address_get(&self) -> Result<String> {
+        self.sysfs_attr_get("address")
+    }
+
+    /// Transport address: PCI BDF for PCIe controller, `traddr` for
+    /// fabrics controller.
+    pub fn traddr_get(&self) -> Result<Option<String>> {
+        let address = self.address_get()?;
+        if self.transport_get()? == NvmeTransport::Pcie {
+            return Ok(Some(address));
+        }
+        Ok(address_field_get(&address, "traddr"))
+    }
+
+    /// Transport service ID of fabrics controller, e.g. TCP port.
+    pub fn trsvcid_get(&self) -> Result<Option<String>> {
+        Ok(address_field_get(&self.address_get()?, "trsvcid"))
+    }
+
+    /// Current state of this controller, read from sysfs on every call.
+    pub fn state_get(&self) -> Result<NvmeCtrlState> {
+        Ok(NvmeCtrlState::from_sysfs(&self.sysfs_attr_get("state")?))
+    }
+
+    /// Number of queues including admin queue.
+    pub fn queue_count_get(&self) -> Result<u32> {
+        self.sysfs_attr_parse("queue_count")
+    }
+
+    /// I/O submission queue size.
+    pub fn sqsize_get(&self) -> Result<u32> {
+        self.sysfs_attr_parse("sqsize")
+    }
+
+    /// NUMA node of this controller, -1 if not NUMA aware.
+    pub fn numa_node_get(&self) -> Result<i32> {
+        self.sysfs_attr_parse("numa_node")
+    }
+
+    /// Host NQN used to connect fabrics controller.
+    pub fn hostnqn_get(&self) -> Result<String> {
+        self.sysfs_attr_get("hostnqn")
+    }
+
+    /// Host ID used to connect fabrics controller.
+    pub fn hostid_get(&self) -> Result<String> {
+        self.sysfs_attr_get("hostid")
+    }
+
+    /// Controller type, e.g. `io`, `discovery` or `admin`. Linux kernel
+    /// prior to 5.18 does not provide this.
+    pub fn cntrltype_get(&self) -> Result<String> {
+        self.sysfs_attr_get("cntrltype")
+    }
+
+    /// Discovery controller type, e.g. `none`, `ddc` or `cdc`. Linux
+    /// kernel prior to 5.18 does not provide this.
+    pub fn dctype_get(&self) -> Result<String> {
+        self.sysfs_attr_get("dctype")
+    }
+
+    /// PCI function of PCIe controller including IDs, link status, NUMA
+    /// node, IOMMU group and physical slot.
+    pub fn pci_info_get(&self) -> Result<NvmePciInfo> {
+        let transport = self.transport_get()?;
+        if transport != NvmeTransport::Pcie {
+            return Err(ErrorKind::NotSupported(format!(
+                "Controller {} is not PCIe but {}", self.name_get(),
+                transport)).into());
+        }
+        NvmePciInfo::from_ctrl_sysfs(&self.root,
+                                     &self.root.ctrl_sysfs_path(
+                                         self.name_get()))
+    }
+
+    /// Temperature sensors of this controller. Linux kernel hwmon sensors
+    /// are used if available which does not issue any admin command,
+    /// otherwise they are read from SMART / Health Information log page.
+    pub fn hwmon_temperatures(&self) -> Result<Vec<NvmeTemperature>> {
+        let temps = NvmeTemperature::from_hwmon(
+            &self.root.ctrl_sysfs_path(self.name_get()))?;
+        if !temps.is_empty() {
+            return Ok(temps);
+        }
+        let data = self.log_page_read(&NvmeGetLogArgs {
+            lid:                NVME_LOG_SMART,
+            nsid:               NVME_NSID_ALL,
+            rae:                true,
+            ..                  Default::default()
+        }, 0, NVME_SMART_LOG_LEN)?;
+        NvmeTemperature::from_smart_log(&data, self.wctemp_get())
+    }
+
+    /// Reset this controller. Namespaces are inaccessible during reset.
+    pub fn reset(&self) -> Result<()> {
+        nvme_ioctl_reset(self.fd_get()?)
+    }
+
+    /// Reset the NVM subsystem this controller belongs to, which impacts
+    /// all controllers of the subsystem. Only works when controller
+    /// supports NVM Subsystem Reset.
+    pub fn subsystem_reset(&self) -> Result<()> {
+        nvme_ioctl_subsys_reset(self.fd_get()?)
+    }
+
+    /// Request linux kernel to rescan namespaces of this controller.
+    pub fn rescan(&self) -> Result<()> {
+        nvme_ioctl_rescan(self.fd_get()?)
+    }
+
+    /// The NVM subsystem this controller belongs to.
+    pub fn subsystem_get(&self) -> Result<NvmeSubsystem> {
+        NvmeSubsystem::from_ctrl_name(self.name_get(), &self.root)
+    }
+
+    /// Block device and generic character device of namespace `nsid`
+    /// attached to this controller. `ErrorKind::NotFound` if the namespace
+    /// has no device node.
+    pub fn ns_dev_paths_get(&self, nsid: u32) -> Result<NvmeNsDevPaths> {
+        NvmeNsDevPaths::resolve(&self.root, self.name_get(), nsid)
+    }
+
     pub fn namespaces_get(&self) -> Result<Vec<NvmeNameSpace>> {
         let mut ret = Vec::new();
-        let blk_path = self.blk_path_get();
-        let nsids = NvmeNameSpace::nsid_list_get(blk_path, self)?;
+        let nsids = NvmeNameSpace::nsid_list_get(self)?;
+        let csi_map = self.nsid_csi_map_get(false);
+        let mut ns_devs = self.ns_devs_get()?;
         for nsid in nsids {
-            let ns = NvmeNameSpace::new(blk_path, self, nsid)?;
-            if ns.blk_path_get().len() == 0 {
-                continue;
+            /* Namespace with only generic character device is included */
+            let ns_dev = match ns_devs.remove(&nsid) {
+                Some(n) => n,
+                None => continue,
+            };
+            let csi = match csi_map.get(&nsid) {
+                Some(c) => *c,
+                None => NvmeCommandSet::Nvm,
+            };
+            ret.push(NvmeNameSpace::new(self, nsid, csi, true, Some(ns_dev))?);
+        }
+        Ok(ret)
+    }
+
+    /* Device nodes of namespaces attached to this controller indexed by
+     * NSID, found by a single scan of controller sysfs folder.
+     */
+    fn ns_devs_get(&self) -> Result<HashMap<u32, NvmeNsDev>> {
+        Ok(NvmeNsDev::ctrl_ns_devs_get(&self.root, self.name_get())?
+           .into_iter()
+           .filter_map(|n| n.nsid_get().map(|nsid| (nsid, n)))
+           .collect())
+    }
+
+    /// Whether the controller supports the Namespace Management and
+    /// Namespace Attachment commands.
+    pub fn ns_mgmt_supported(&self) -> bool {
+        self.oacs_get() & NVME_OACS_NS_MGMT != 0
+    }
+
+    /// Query all namespaces allocated in the NVM subsystem including those
+    /// not attached to this controller. Use `NvmeNameSpace::is_attached()`
+    /// to check the attach state. Unlike `namespaces_get()`, namespaces
+    /// without block device are also included.
+    pub fn allocated_namespaces_get(&self) -> Result<Vec<NvmeNameSpace>> {
+        if !self.ns_mgmt_supported() {
+            return Err(ErrorKind::NotSupported(format!(
+                "Controller {} does not support namespace management",
+                self.blk_path_get())).into());
+        }
+        let mut ret = Vec::new();
+        let active_nsids: HashSet<u32> =
+            NvmeNameSpace::nsid_list_get(self)?.into_iter().collect();
+        let csi_map = self.nsid_csi_map_get(true);
+        let mut ns_devs = self.ns_devs_get()?;
+        for nsid in NvmeNameSpace::allocated_nsid_list_get(self)? {
+            let attached = active_nsids.contains(&nsid);
+            let csi = match csi_map.get(&nsid) {
+                Some(c) => *c,
+                None => NvmeCommandSet::Nvm,
+            };
+            /* Detached namespace has no device node */
+            let ns_dev = if attached {
+                ns_devs.remove(&nsid)
+            } else {
+                None
+            };
+            ret.push(NvmeNameSpace::new(self, nsid, csi, attached, ns_dev)?);
+        }
+        Ok(ret)
+    }
+
+    /* Namespaces not listed in any non-NVM command set are NVM namespaces.
+     * Controllers prior to NVMe 2.0 only support the NVM command set.
+     * With `allocated`, the allocated namespace lists (CNS 1Ah) are used
+     * to cover namespaces not attached to this controller, otherwise the
+     * active ones (CNS 07h).
+     */
+    fn nsid_csi_map_get(&self, allocated: bool)
+        -> HashMap<u32, NvmeCommandSet> {
+        let mut ret = HashMap::new();
+        if self.ver_get() < NvmeController::ver_gen(2, 0, 0) {
+            return ret;
+        }
+        for csi in &[NvmeCommandSet::KeyValue, NvmeCommandSet::Zoned] {
+            /* Controller reports invalid field for unsupported command set */
+            let nsids = if allocated {
+                NvmeNameSpace::allocated_nsid_list_csi_get(self, *csi)
+            } else {
+                NvmeNameSpace::nsid_list_csi_get(self, *csi)
+            };
+            if let Ok(nsids) = nsids {
+                for nsid in nsids {
+                    ret.insert(nsid, *csi);
+                }
             }
-            ret.push(ns);
         }
+        ret
+    }
+
+    /// Query the I/O Command Set specific Identify Controller data
+    /// structure (CNS 06h). The layout of returned 4096 bytes depends on
+    /// the command set.
+    pub fn id_ctrl_csi_get(&self, csi: NvmeCommandSet) -> Result<Vec<u8>> {
+        let fd = self.fd_get()?;
+        let mut data = vec![0u8; NVME_IDENTIFY_DATA_LEN];
+        nvme_ioctl_identify(fd, NVME_ADMIN_CMD_CNS_CSI_CTRL, 0, 0,
+                            csi.csi(), &mut data)?;
+        Ok(data)
+    }
+
+    /// Query the Identify I/O Command Set data structure (CNS 1Ch).
+    /// Each returned u64 is a I/O Command Set combination vector which
+    /// could be checked by `NvmeCommandSet::is_in_vector()`. The index of
+    /// vector could be used by `io_cmd_set_profile_set()`. Trailing empty
+    /// vectors are not included.
+    pub fn io_cmd_set_combinations_get(&self) -> Result<Vec<u64>> {
+        let fd = self.fd_get()?;
+        let mut data = vec![0u8; NVME_IDENTIFY_DATA_LEN];
+        nvme_ioctl_identify(fd, NVME_ADMIN_CMD_CNS_IO_CMD_SET, 0,
+                            self.cntlid_get(), NVME_CSI_NVM, &mut data)?;
+        let mut ret = Vec::with_capacity(NVME_IO_CMD_SET_COMBINATION_COUNT);
+        for chunk in data.chunks(8) {
+            let mut vector = [0u8; 8];
+            vector.copy_from_slice(chunk);
+            ret.push(to_u64(vector));
+        }
+        while ret.last() == Some(&0) {
+            ret.pop();
+        }
+        Ok(ret)
+    }
+
+    /// Query the index of currently selected I/O Command Set combination
+    /// via the I/O Command Set Profile feature (FID 19h).
+    pub fn io_cmd_set_profile_get(&self) -> Result<u16> {
+        let fd = self.fd_get()?;
+        let result = nvme_ioctl_get_feature(
+            fd, NVME_FEAT_IO_CMD_SET_PROFILE, 0, 0)?;
+        Ok((result & NVME_FEAT_IO_CMD_SET_PROFILE_MASK) as u16)
+    }
+
+    /// Select the I/O Command Set combination by index of
+    /// `io_cmd_set_combinations_get()`. The controller only accept this
+    /// when it is disabled, so this normally fails on a live controller.
+    pub fn io_cmd_set_profile_set(&self, index: u16) -> Result<()> {
+        if u32::from(index) > NVME_FEAT_IO_CMD_SET_PROFILE_MASK {
+            return Err(ErrorKind::InvalidArgument(format!(
+                "Invalid I/O Command Set combination index {}, should be \
+                 less than {}", index, NVME_IO_CMD_SET_COMBINATION_COUNT))
+                .into());
+        }
+        let fd = self.fd_get()?;
+        nvme_ioctl_set_feature(fd, NVME_FEAT_IO_CMD_SET_PROFILE, 0,
+                               u32::from(index), false)?;
+        Ok(())
+    }
+
+    /* Shared file descriptor of controller */
+    pub(crate) fn fd_share(&self) -> Result<Arc<NvmeDevFd>> {
+        match self.fd {
+            Some(ref fd) => Ok(fd.clone()),
+            None => Err(ErrorKind::InvalidArgument(format!(
+                "Controller {} is closed", self.blk_path)).into()),
+        }
+    }
+
+    pub(crate) fn fd_get(&self) -> Result<&NvmeDevFd> {
+        match self.fd {
+            Some(ref fd) => Ok(fd),
+            None => Err(ErrorKind::InvalidArgument(format!(
+                "Controller {} is closed", self.blk_path)).into()),
+        }
+    }
+
+    /// Release the controller file descriptor, any later command on this
+    /// controller will fail. Namespaces created from this controller hold
+    /// their own reference, the file descriptor is closed after all of
+    /// them are dropped. Dropping the controller has the same effect.
+    pub fn close(&mut self) {
+        self.fd = None;
+    }
+
+    /// Open io_uring transport of `depth` entries on controller character
+    /// device for batched admin passthrough, commands are checked against
+    /// `opcode_policy_get()`.
+    #[cfg(feature = "io_uring")]
+    pub fn uring_transport_open(&self, depth: u32)
+        -> Result<NvmeUringTransport> {
+        NvmeUringTransport::open_with_opts(
+            &self.root.dev_path(self.name_get()), depth,
+            self.fd_get()?.opts_get().clone())
+    }
+
+    /// Options of admin command `opcode`, `NvmeCmdOpts::admin_default()`
+    /// unless changed by `admin_cmd_opts_set()`.
+    pub fn admin_cmd_opts_get(&self, opcode: u8) -> Result<NvmeCmdOpts> {
+        Ok(self.fd_get()?.opts_get().get(true, opcode))
+    }
+
+    /// Change options of admin command `opcode` issued by this controller
+    /// and its namespaces.
+    pub fn admin_cmd_opts_set(&self, opcode: u8, opts: NvmeCmdOpts)
+        -> Result<()> {
+        self.fd_get()?.opts_get().set(true, opcode, opts);
+        Ok(())
+    }
+
+    /// Options of I/O command `opcode`, `NvmeCmdOpts::io_default()`
+    /// unless changed by `io_cmd_opts_set()`.
+    pub fn io_cmd_opts_get(&self, opcode: u8) -> Result<NvmeCmdOpts> {
+        Ok(self.fd_get()?.opts_get().get(false, opcode))
+    }
+
+    /// Change options of I/O command `opcode` issued via namespaces of
+    /// this controller.
+    pub fn io_cmd_opts_set(&self, opcode: u8, opts: NvmeCmdOpts)
+        -> Result<()> {
+        self.fd_get()?.opts_get().set(false, opcode, opts);
+        Ok(())
+    }
+
+    /// Opcode policy of `admin_passthru()` and `NvmeNameSpace::io_passthru()`
+    /// of this controller and its namespaces.
+    pub fn opcode_policy_get(&self) -> Result<NvmeOpcodePolicy> {
+        Ok(self.fd_get()?.opts_get().policy_get())
+    }
+
+    pub fn opcode_policy_set(&self, policy: NvmeOpcodePolicy) -> Result<()> {
+        self.fd_get()?.opts_get().policy_set(policy);
+        Ok(())
+    }
+
+    /// Issue admin command, e.g. vendor specific opcode C0h-FFh. The
+    /// `dir` should match the transfer direction encoded in opcode and
+    /// `buffer` should be empty only when no data is transferred,
+    /// `metadata` is the separate metadata buffer if the command uses it.
+    /// Command denied by `opcode_policy_set()` fails with
+    /// `ErrorKind::PermissionDenied`, command marked unsupported fails with
+    /// `ErrorKind::NotSupported` if `unsupported_reject_set()` of opcode
+    /// policy is enabled. NVMe status is returned in the completion
+    /// instead of error.
+    pub fn admin_passthru(&self, cmd: &NvmeCmd, dir: NvmeDataDirection,
+                          buffer: &mut [u8], metadata: Option<&mut [u8]>)
+        -> Result<NvmeCompletion> {
+        let fd = self.fd_get()?;
+        let policy = fd.opts_get().policy_get();
+        let mut no_metadata = [0u8; 0];
+        let metadata = metadata.unwrap_or(&mut no_metadata);
+        passthru_check(&policy, true, cmd, dir, buffer.len(),
+                       metadata.len())?;
+        if policy.unsupported_reject_get() &&
+           self.admin_cmd_supported(cmd.opcode)? == Some(false) {
+            return Err(ErrorKind::NotSupported(format!(
+                "Admin opcode 0x{:02x} is not supported by controller {}",
+                cmd.opcode, self.name_get())).into());
+        }
+        nvme_ioctl_admin_passthru(fd, cmd.to_passthru_with_metadata(
+            buffer, metadata))
+    }
+
+    /// Query the Commands Supported and Effects log page (05h). The admin
+    /// commands part is identical for all command sets, the I/O commands
+    /// part is for specified command set.
+    pub fn cmd_effects_log_get(&self, csi: NvmeCommandSet)
+        -> Result<NvmeCmdEffectsLog> {
+        if self.is_log_supported(NVME_LOG_CMD_EFFECTS)? == Some(false) {
+            return Err(ErrorKind::NotSupported(format!(
+                "Controller {} does not support Commands Supported and \
+                 Effects log page", self.name_get())).into());
+        }
+        let data = self.log_page_read(&NvmeGetLogArgs {
+            lid:                NVME_LOG_CMD_EFFECTS,
+            csi:                csi.csi(),
+            ..                  Default::default()
+        }, 0, NVME_CMD_EFFECTS_LOG_LEN)?;
+        let log = NvmeCmdEffectsLog::parse(&data)?;
+        if csi == NvmeCommandSet::Nvm {
+            *self.cmd_effects_lock() = Some(log.clone());
+        }
+        Ok(log)
+    }
+
+    /* Whether admin command `opcode` is supported according to cached
+     * Commands Supported and Effects log page, None if controller does
+     * not support that log page.
+     */
+    fn admin_cmd_supported(&self, opcode: u8) -> Result<Option<bool>> {
+        if self.is_log_supported(NVME_LOG_CMD_EFFECTS)? == Some(false) {
+            return Ok(None);
+        }
+        if let Some(ref log) = *self.cmd_effects_lock() {
+            return Ok(Some(log.admin_effects_get(opcode).is_supported()));
+        }
+        match self.cmd_effects_log_get(NvmeCommandSet::Nvm) {
+            Ok(log) =>
+                Ok(Some(log.admin_effects_get(opcode).is_supported())),
+            Err(NvmeError{kind: ErrorKind::NvmeStatus(c, _)})
+                if c.is_invalid_cmd() => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn cmd_effects_lock(&self)
+        -> MutexGuard<'_, Option<NvmeCmdEffectsLog>> {
+        mutex_lock(&self.cmd_effects)
+    }
+
+    /// Query the Supported Log Pages log page (00h) of NVMe 2.0, or of
+    /// earlier controller indicating it in Log Page Attributes.
+    pub fn supported_log_pages_get(&self) -> Result<NvmeSupportedLogPages> {
+        if self.ver_get() < NvmeController::ver_gen(2, 0, 0) &&
+           self.lpa_get() & NVME_LPA_SUPPORTED_LOG == 0 {
+            return Err(ErrorKind::NotSupported(format!(
+                "Controller {} of NVMe version {} does not support \
+                 Supported Log Pages log page", self.name_get(),
+                self.ver_str)).into());
+        }
+        let data = self.log_page_read(&NvmeGetLogArgs {
+            lid:                NVME_LOG_SUPPORTED,
+            ..                  Default::default()
+        }, 0, NVME_SUPPORTED_LOG_LEN)?;
+        NvmeSupportedLogPages::parse(&data)
+    }
+
+    /// Whether log page `lid` is supported without issuing that command.
+    /// Supported Log Pages log page is queried once and cached. For
+    /// controller without it, mandatory log pages and the ones indicated
+    /// by Log Page Attributes are reported as supported while others are
+    /// unknown (None), the caller should try the command then.
+    pub fn is_log_supported(&self, lid: u8) -> Result<Option<bool>> {
+        if let Some(ref logs) = *mutex_lock(&self.supported_logs) {
+            return Ok(logs.support_get(lid));
+        }
+        let logs = match self.supported_log_pages_get() {
+            Ok(logs) => logs,
+            Err(NvmeError{kind: ErrorKind::NotSupported(_)}) =>
+                NvmeSupportedLogPages::from_ctrl_attrs(
+                    self.lpa_get(), self.ana_supported()),
+            Err(NvmeError{kind: ErrorKind::NvmeStatus(c, _)})
+                if c.is_invalid_cmd() =>
+                NvmeSupportedLogPages::from_ctrl_attrs(
+                    self.lpa_get(), self.ana_supported()),
+            Err(e) => return Err(e),
+        };
+        let ret = logs.support_get(lid);
+        *mutex_lock(&self.supported_logs) = Some(logs);
         Ok(ret)
     }
 
+    /// Query the Feature Identifiers Supported and Effects log page (12h).
+    pub fn fid_effects_log_get(&self) -> Result<NvmeFidEffectsLog> {
+        if self.is_log_supported(NVME_LOG_FID_EFFECTS)? == Some(false) {
+            return Err(ErrorKind::NotSupported(format!(
+                "Controller {} does not support FID Supported and Effects \
+                 log page", self.name_get())).into());
+        }
+        let data = self.log_page_read(&NvmeGetLogArgs {
+            lid:                NVME_LOG_FID_EFFECTS,
+            ..                  Default::default()
+        }, 0, NVME_FID_EFFECTS_LOG_LEN)?;
+        NvmeFidEffectsLog::parse(&data)
+    }
+
     pub fn from_path(blk_path: &str) -> Result<NvmeController> {
-        let fd = nvme_ioctl_fd_open(blk_path)?;
+        NvmeController::from_path_with_root(blk_path, &SystemRoot::default())
+    }
+
+    /// Same as `from_path()` but namespace discovery is done in specified
+    /// sysfs and device root folders.
+    pub fn from_path_with_root(blk_path: &str, root: &SystemRoot)
+        -> Result<NvmeController> {
+        NvmeController::from_file(nvme_ioctl_fd_open(blk_path)?, blk_path,
+                                  root)
+    }
+
+    /// Create controller from a file descriptor of NVMe controller
+    /// character device already opened by caller, for example passed in
+    /// by systemd socket activation or from a privileged helper.
+    /// The controller takes the ownership of the file descriptor.
+    pub fn from_fd(fd: File) -> Result<NvmeController> {
+        /* Failure is tolerated as the file descriptor might be passed in
+         * from other mount namespace.
+         */
+        let blk_path = match read_link(
+            format!("/proc/self/fd/{}", fd.as_raw_fd())) {
+            Ok(p) => p.to_string_lossy().to_string(),
+            Err(_) => String::new(),
+        };
+        NvmeController::from_file(fd, &blk_path, &SystemRoot::default())
+    }
 
+    fn from_file(fd: File, blk_path: &str, root: &SystemRoot)
+        -> Result<NvmeController> {
+        let fd = NvmeDevFd::new(fd);
         let mut id_data: NvmeSpecIdCtrlData = Default::default();
 
         let nvme_cmd = NvmeAdminCmd {
@@ -151,6 +1118,10 @@ impl NvmeController {
                               id_data.ver[0]);
         }
 
+        let name = ctrl_name_resolve(root, &fd, blk_path);
+        fd.opts_get().crdt_set([to_u16(id_data.crdt1), to_u16(id_data.crdt2),
+                                to_u16(id_data.crdt3)]);
+
         Ok(NvmeController {
             sn:             str::from_utf8(&id_data.sn)?.trim().to_string(),
             mn:             str::from_utf8(&id_data.mn)?.trim().to_string(),
@@ -158,35 +1129,158 @@ impl NvmeController {
             fguid:          str::from_utf8(&id_data.fguid)?.trim().to_string(),
             subnqn:         str::from_utf8(&id_data.subnqn)?.trim().to_string(),
             raw_id_data:    id_data,
-            ver_str:        ver_str,
-            blk_path:       format!("{}", blk_path)})
+            ver_str,
+            blk_path:       blk_path.to_string(),
+            name,
+            fd:             Some(Arc::new(fd)),
+            root:           root.clone(),
+            cmd_effects:    Mutex::new(None),
+            supported_logs: Mutex::new(None)})
+    }
+
+    /* Device paths of all NVMe controllers found by sysfs discovery */
+    fn ctrl_blk_paths_get(root: &SystemRoot) -> Vec<String> {
+        NvmeCtrlDev::get_all_with_root(root).into_iter()
+            .map(|c| c.dev_path_get().to_string())
+            .collect()
     }
 
+    /// Enumerate all NVMe controllers, fail if any controller failed.
+    /// Use `scan()` to tolerate per-controller failures.
     pub fn get_all() -> Result<Vec<NvmeController>> {
+        NvmeController::get_all_with_root(&SystemRoot::default())
+    }
+
+    /// Same as `get_all()` but in specified sysfs and device root folders.
+    pub fn get_all_with_root(root: &SystemRoot)
+        -> Result<Vec<NvmeController>> {
         let mut ret = Vec::new();
-        let mut ctrl_names = Vec::new();
-        match read_dir(SYSFS_NVME_CTRL_FOLDER) {
-            Err(_) => return Ok(ret),
-            Ok(paths) => for path in  paths {
-                match path {
-                    /* Got error when interate, it might happen when
-                     * nvme controler got removed after we open that dir
-                     */
-                    Err(_) => continue,
-                    Ok(dir_entry) => ctrl_names.push(dir_entry.file_name()
-                                                     .into_string().unwrap())
-                };
+        for blk_path in NvmeController::ctrl_blk_paths_get(root) {
+            ret.push(NvmeController::from_path_with_root(&blk_path, root)?);
+        }
+        Ok(ret)
+    }
+
+    /// Enumerate all NVMe controllers. A failure on one controller (e.g.
+    /// permission denied, fabrics controller reconnecting or dead drive)
+    /// is reported in `NvmeScanResult.failures` instead of failing the
+    /// whole scan.
+    pub fn scan() -> NvmeScanResult {
+        NvmeController::scan_with_root(&SystemRoot::default())
+    }
+
+    /// Same as `scan()` but in specified sysfs and device root folders.
+    pub fn scan_with_root(root: &SystemRoot) -> NvmeScanResult {
+        let mut ret = NvmeScanResult::default();
+        for blk_path in NvmeController::ctrl_blk_paths_get(root) {
+            match NvmeController::from_path_with_root(&blk_path, root) {
+                Ok(ctrl) => ret.controllers.push(ctrl),
+                Err(error) =>
+                    ret.failures.push(NvmeScanFailure{blk_path, error}),
             }
-        };
-        for ctrl_name in ctrl_names {
-            let ref blk_path = format!("/dev/{}", ctrl_name);
-            /* Skip if /dev/nvmeX does not exists */
-            if !Path::new(blk_path).exists() {
-                continue;
+        }
+        ret
+    }
+
+    /// Enumerate all NVMe controllers concurrently by a limited number of
+    /// worker threads. Failures are reported the same way as `scan()`.
+    pub fn get_all_parallel() -> NvmeScanResult {
+        NvmeController::get_all_parallel_with_root(&SystemRoot::default())
+    }
+
+    /// Same as `get_all_parallel()` but in specified sysfs and device root
+    /// folders.
+    pub fn get_all_parallel_with_root(root: &SystemRoot) -> NvmeScanResult {
+        let blk_paths = NvmeController::ctrl_blk_paths_get(root);
+        let worker_count = thread::available_parallelism()
+            .map(|n| n.get()).unwrap_or(1)
+            .clamp(1, SCAN_WORKER_MAX)
+            .min(blk_paths.len());
+
+        /* Workers take the next index from `queue` and store the result at
+         * the same index, so the order matches `scan()`.
+         */
+        let queue = Arc::new(Mutex::new(0usize));
+        let results: Arc<Mutex<Vec<Option<Result<NvmeController>>>>> =
+            Arc::new(Mutex::new(blk_paths.iter().map(|_| None).collect()));
+        let blk_paths = Arc::new(blk_paths);
+        let mut workers = Vec::with_capacity(worker_count);
+        let mut spawn_error = None;
+        for _ in 0..worker_count {
+            let queue = queue.clone();
+            let results = results.clone();
+            let blk_paths = blk_paths.clone();
+            let root = root.clone();
+            match thread::Builder::new().name("nvme-scan".to_string())
+                .spawn(move || loop {
+                    let index = {
+                        let mut next = mutex_lock(&queue);
+                        let index = *next;
+                        *next += 1;
+                        index
+                    };
+                    if index >= blk_paths.len() {
+                        break;
+                    }
+                    let ctrl = NvmeController::from_path_with_root(
+                        &blk_paths[index], &root);
+                    mutex_lock(&results)[index] = Some(ctrl);
+                }) {
+                Ok(handle) => workers.push(handle),
+                Err(e) => {
+                    spawn_error = Some(e);
+                    break;
+                },
             }
-            ret.push(NvmeController::from_path(blk_path)?);
         }
-        Ok(ret)
+        /* Panic of a worker leaves its controller without result */
+        for worker in workers {
+            let _ = worker.join();
+        }
+
+        let mut ret = NvmeScanResult::default();
+        let results = std::mem::take(&mut *mutex_lock(&results));
+        for (blk_path, result) in blk_paths.iter().zip(results) {
+            let blk_path = blk_path.to_string();
+            match result {
+                Some(Ok(ctrl)) => ret.controllers.push(ctrl),
+                Some(Err(error)) =>
+                    ret.failures.push(NvmeScanFailure{blk_path, error}),
+                None => ret.failures.push(NvmeScanFailure{
+                    blk_path,
+                    error:      match spawn_error {
+                        Some(ref e) => ErrorKind::LibBug(format!(
+                            "Failed to create controller scan thread: {}",
+                            e)),
+                        None => ErrorKind::LibBug(
+                            "Controller scan thread panicked".to_string()),
+                    }.into(),
+                }),
+            }
+        }
+        ret
     }
 }
 
+/* Match device number of opened character device against `dev` sysfs
+ * attribute of controllers, so renamed device nodes and symbolic links
+ * still resolve to kernel name. Fallback to basename of `blk_path`.
+ */
+fn ctrl_name_resolve(root: &SystemRoot, fd: &File, blk_path: &str)
+    -> String {
+    if let Ok(metadata) = fd.metadata() {
+        let rdev = metadata.rdev();
+        let dev_num = format!("{}:{}", libc::major(rdev), libc::minor(rdev));
+        let ctrl_names = dir_entry_names_get(
+            &root.sysfs_path(SYSFS_NVME_CTRL_FOLDER)).unwrap_or_default();
+        for ctrl_name in ctrl_names.into_iter().filter(|n| is_ctrl_name(n)) {
+            if sysfs_read_str(&format!("{}/dev",
+                                       root.ctrl_sysfs_path(&ctrl_name)))
+                .ok().as_deref() == Some(dev_num.as_str()) {
+                return ctrl_name;
+            }
+        }
+    }
+    Path::new(blk_path).file_name().and_then(|n| n.to_str())
+        .unwrap_or("").to_string()
+}
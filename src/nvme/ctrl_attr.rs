@@ -0,0 +1,124 @@
+/*
+ * Copyright (C) 2017 Red Hat, Inc.
+ * This library is free software; you can redistribute it and/or
+ * modify it under the terms of the GNU Lesser General Public
+ * License as published by the Free Software Foundation; either
+ * version 2.1 of the License, or (at your option) any later version.
+ *
+ * This library is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+ * Lesser General Public License for more details.
+ *
+ * You should have received a copy of the GNU Lesser General Public
+ * License along with this library; If not, see <http://www.gnu.org/licenses/>.
+ *
+ * Author: Gris Ge <fge@redhat.com>
+ */
+
+use std::fmt;
+
+/// Transport of NVMe controller as linux kernel shows in
+/// `/sys/class/nvme/nvmeX/transport`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NvmeTransport {
+    Pcie,
+    Tcp,
+    Rdma,
+    Fc,
+    Loop,
+    Unknown(String),
+}
+
+impl NvmeTransport {
+    pub(crate) fn from_sysfs(transport: &str) -> NvmeTransport {
+        match transport {
+            "pcie" => NvmeTransport::Pcie,
+            "tcp" => NvmeTransport::Tcp,
+            "rdma" => NvmeTransport::Rdma,
+            "fc" => NvmeTransport::Fc,
+            "loop" => NvmeTransport::Loop,
+            _ => NvmeTransport::Unknown(transport.to_string()),
+        }
+    }
+
+    /// Whether this is a known NVMe over Fabrics transport. False for
+    /// `Unknown`.
+    pub fn is_fabrics(&self) -> bool {
+        match *self {
+            NvmeTransport::Tcp | NvmeTransport::Rdma | NvmeTransport::Fc |
+            NvmeTransport::Loop => true,
+            NvmeTransport::Pcie | NvmeTransport::Unknown(_) => false,
+        }
+    }
+}
+
+impl fmt::Display for NvmeTransport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            NvmeTransport::Pcie => write!(f, "pcie"),
+            NvmeTransport::Tcp => write!(f, "tcp"),
+            NvmeTransport::Rdma => write!(f, "rdma"),
+            NvmeTransport::Fc => write!(f, "fc"),
+            NvmeTransport::Loop => write!(f, "loop"),
+            NvmeTransport::Unknown(ref t) => write!(f, "{}", t),
+        }
+    }
+}
+
+/// State of NVMe controller as linux kernel shows in
+/// `/sys/class/nvme/nvmeX/state`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NvmeCtrlState {
+    New,
+    Live,
+    Resetting,
+    Connecting,
+    Deleting,
+    DeletingNoIo,
+    Dead,
+    Unknown(String),
+}
+
+impl NvmeCtrlState {
+    pub(crate) fn from_sysfs(state: &str) -> NvmeCtrlState {
+        match state {
+            "new" => NvmeCtrlState::New,
+            "live" => NvmeCtrlState::Live,
+            "resetting" => NvmeCtrlState::Resetting,
+            "connecting" => NvmeCtrlState::Connecting,
+            "deleting" => NvmeCtrlState::Deleting,
+            "deleting (no IO)" => NvmeCtrlState::DeletingNoIo,
+            "dead" => NvmeCtrlState::Dead,
+            _ => NvmeCtrlState::Unknown(state.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for NvmeCtrlState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            NvmeCtrlState::New => write!(f, "new"),
+            NvmeCtrlState::Live => write!(f, "live"),
+            NvmeCtrlState::Resetting => write!(f, "resetting"),
+            NvmeCtrlState::Connecting => write!(f, "connecting"),
+            NvmeCtrlState::Deleting => write!(f, "deleting"),
+            NvmeCtrlState::DeletingNoIo => write!(f, "deleting (no IO)"),
+            NvmeCtrlState::Dead => write!(f, "dead"),
+            NvmeCtrlState::Unknown(ref s) => write!(f, "{}", s),
+        }
+    }
+}
+
+/* Fabrics address is like `traddr=192.168.1.1,trsvcid=4420`, PCIe address
+ * is the PCI BDF.
+ */
+pub(crate) fn address_field_get(address: &str, key: &str) -> Option<String> {
+    for field in address.split(',') {
+        let mut kv = field.splitn(2, '=');
+        if kv.next().map(|k| k.trim()) == Some(key) {
+            return kv.next().map(|v| v.trim().to_string());
+        }
+    }
+    None
+}
@@ -0,0 +1,484 @@
+/*
+ * Copyright (C) 2017 Red Hat, Inc.
+ * This library is free software; you can redistribute it and/or
+ * modify it under the terms of the GNU Lesser General Public
+ * License as published by the Free Software Foundation; either
+ * version 2.1 of the License, or (at your option) any later version.
+ *
+ * This library is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+ * Lesser General Public License for more details.
+ *
+ * You should have received a copy of the GNU Lesser General Public
+ * License along with this library; If not, see <http://www.gnu.org/licenses/>.
+ *
+ * Author: Gris Ge <fge@redhat.com>
+ */
+
+use std::fs::canonicalize;
+use std::path::Path;
+
+use super::controller::NvmeController;
+use super::error::*;
+use super::ioctl::{nvme_ioctl_fd_open, nvme_ioctl_id};
+use super::ns_dev::NvmeNsDevPaths;
+use super::root::SystemRoot;
+use super::utils::{dir_entry_names_get, sysfs_read_str};
+
+pub(crate) static SYSFS_NVME_CTRL_FOLDER: &str = "class/nvme";
+static SYSFS_BLOCK_FOLDER: &str = "class/block";
+static SYSFS_NVME_GENERIC_FOLDER: &str = "class/nvme-generic";
+
+/// NVMe controller found in sysfs with its device node. Discovery only
+/// reads sysfs and device folders, no NVMe command is issued until
+/// `open()`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NvmeCtrlDev {
+    name:                       String,
+    dev_path:                   String,
+    root:                       SystemRoot,
+}
+
+impl NvmeCtrlDev {
+    /// Kernel name of controller, e.g. `nvme0`.
+    pub fn name_get(&self) -> &str { &self.name }
+    /// Device node of controller, e.g. `/dev/nvme0`.
+    pub fn dev_path_get(&self) -> &str { &self.dev_path }
+
+    pub fn get_all() -> Vec<NvmeCtrlDev> {
+        NvmeCtrlDev::get_all_with_root(&SystemRoot::default())
+    }
+
+    /// Controllers in specified sysfs and device root folders. Controller
+    /// without device node is skipped.
+    pub fn get_all_with_root(root: &SystemRoot) -> Vec<NvmeCtrlDev> {
+        /* Got error when interate, it might happen when nvme controler got
+         * removed after we open that dir
+         */
+        dir_entry_names_get(&root.sysfs_path(SYSFS_NVME_CTRL_FOLDER))
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|n| is_ctrl_name(n))
+            .filter_map(|name| {
+                dev_node_find(root, SYSFS_NVME_CTRL_FOLDER, &name)
+                    .map(|dev_path| NvmeCtrlDev {
+                        name,
+                        dev_path,
+                        root:   root.clone(),
+                    })
+            })
+            .collect()
+    }
+
+    /// Namespaces attached to this controller which have device node.
+    pub fn namespaces_get(&self) -> Result<Vec<NvmeNsDev>> {
+        NvmeNsDev::ctrl_ns_devs_get(&self.root, &self.name)
+    }
+
+    /// Open controller device node and query it.
+    pub fn open(&self) -> Result<NvmeController> {
+        NvmeController::from_path_with_root(&self.dev_path, &self.root)
+    }
+}
+
+/// Namespace device nodes found in sysfs, multipath head when kernel
+/// native NVMe multipath is used.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NvmeNsDev {
+    name:                       String,
+    nsid:                       Option<u32>,
+    dev_paths:                  NvmeNsDevPaths,
+    path_names:                 Vec<String>,
+}
+
+impl NvmeNsDev {
+    /// Kernel name, e.g. `nvme0n1`, or `ng0n1` when namespace has only
+    /// generic character device.
+    pub fn name_get(&self) -> &str { &self.name }
+    /// None if neither sysfs nor device node provides it.
+    pub fn nsid_get(&self) -> Option<u32> { self.nsid }
+    pub fn dev_paths_get(&self) -> &NvmeNsDevPaths { &self.dev_paths }
+    /// Kernel names of multipath paths, e.g. `nvme0c1n1`, empty if not
+    /// multipath.
+    pub fn path_names_get(&self) -> &[String] { &self.path_names }
+
+    /* Namespaces `nvmeXnY` private to controller `ctrl_name`, multipath
+     * heads `nvmeXnZ` of paths `nvmeXcYnZ`, then generic character devices
+     * `ngXnY` without block device.
+     */
+    pub(crate) fn ctrl_ns_devs_get(root: &SystemRoot, ctrl_name: &str)
+        -> Result<Vec<NvmeNsDev>> {
+        let ctrl_sysfs_path = root.ctrl_sysfs_path(ctrl_name);
+        let entries = dir_entry_names_get(&ctrl_sysfs_path)?;
+        let mut ret: Vec<NvmeNsDev> = Vec::new();
+        for entry in &entries {
+            let head = if is_ns_name(entry) {
+                entry.to_string()
+            } else if let Some(head) = path_head_name(entry) {
+                head
+            } else {
+                continue;
+            };
+            let nsid = ns_nsid_get(root, &head, &format!(
+                "{}/{}", ctrl_sysfs_path, entry));
+            ret.push(NvmeNsDev {
+                dev_paths:      NvmeNsDevPaths::new(
+                    dev_node_find(root, SYSFS_BLOCK_FOLDER, &head),
+                    dev_node_find(root, SYSFS_NVME_GENERIC_FOLDER,
+                                  &generic_name(&head))),
+                path_names:     dir_entry_names_get(&root.sysfs_path(
+                    &format!("block/{}/multipath", head)))
+                    .unwrap_or_default(),
+                name:           head,
+                nsid,
+            });
+        }
+        for entry in entries.iter().filter(|e| is_generic_name(e)) {
+            /* Generic device of a path is `ng<ctrl>n<head>` while its block
+             * device is `nvme<subsys>c<ctrl>n<head>`, so only the namespace
+             * head instance is shared within this controller.
+             */
+            if ret.iter().any(|n| ns_instance_get(&n.name) ==
+                                  ns_instance_get(entry)) {
+                continue;
+            }
+            let generic_path = dev_node_find(root, SYSFS_NVME_GENERIC_FOLDER,
+                                             entry);
+            /* Generic character device has no `nsid` sysfs attribute */
+            let nsid = generic_path.as_ref()
+                .and_then(|p| nvme_ioctl_fd_open(p).ok())
+                .and_then(|fd| nvme_ioctl_id(&fd).ok());
+            if nsid.is_some() && ret.iter().any(|n| n.nsid == nsid) {
+                continue;
+            }
+            ret.push(NvmeNsDev {
+                name:           entry.to_string(),
+                nsid,
+                dev_paths:      NvmeNsDevPaths::new(None, generic_path),
+                path_names:     Vec::new(),
+            });
+        }
+        ret.retain(|n| !n.dev_paths.is_empty());
+        Ok(ret)
+    }
+
+    /* Namespace `nsid` attached to controller `ctrl_name`,
+     * ErrorKind::NotFound if the namespace has no device node.
+     */
+    pub(crate) fn resolve(root: &SystemRoot, ctrl_name: &str, nsid: u32)
+        -> Result<NvmeNsDev> {
+        match NvmeNsDev::ctrl_ns_devs_get(root, ctrl_name)?.into_iter()
+            .find(|n| n.nsid == Some(nsid)) {
+            Some(n) => Ok(n),
+            None => Err(ErrorKind::NotFound(format!(
+                "No device node found for namespace {} of controller {}",
+                nsid, ctrl_name)).into()),
+        }
+    }
+}
+
+/* Controller name is `nvme` followed by the instance number */
+pub(crate) fn is_ctrl_name(name: &str) -> bool {
+    name.len() > "nvme".len() && name.starts_with("nvme") &&
+        name["nvme".len()..].bytes().all(|c| c.is_ascii_digit())
+}
+
+/* Namespace block device name is `nvmeXnY` */
+pub(crate) fn is_ns_name(name: &str) -> bool {
+    if !name.starts_with("nvme") {
+        return false;
+    }
+    let parts: Vec<&str> = name["nvme".len()..].split('n').collect();
+    parts.len() == 2 && parts.iter().all(|p| {
+        !p.is_empty() && p.bytes().all(|c| c.is_ascii_digit())
+    })
+}
+
+/* Multipath path `nvme<subsys>c<ctrl>n<ns>` to its head `nvme<subsys>n<ns>`
+ */
+pub(crate) fn path_head_name(name: &str) -> Option<String> {
+    let rest = name.strip_prefix("nvme")?;
+    let c_pos = rest.find('c')?;
+    let n_pos = rest.rfind('n')?;
+    if c_pos >= n_pos {
+        return None;
+    }
+    let head = format!("nvme{}{}", &rest[..c_pos], &rest[n_pos..]);
+    let ctrl_id = &rest[c_pos + 1..n_pos];
+    if is_ns_name(&head) && !ctrl_id.is_empty() &&
+       ctrl_id.bytes().all(|c| c.is_ascii_digit()) {
+        Some(head)
+    } else {
+        None
+    }
+}
+
+/* Block device `nvmeXnY` and generic device `ngXnY` share the instance
+ * numbers.
+ */
+fn generic_name(ns_name: &str) -> String {
+    format!("ng{}", &ns_name["nvme".len()..])
+}
+
+/* Namespace head instance, the number after the last `n` of `nvmeXnY`,
+ * `nvmeXcYnZ` or `ngXnY`.
+ */
+fn ns_instance_get(name: &str) -> Option<&str> {
+    name.rfind('n').map(|i| &name[i + 1..])
+}
+
+fn is_generic_name(name: &str) -> bool {
+    match name.strip_prefix("ng") {
+        Some(rest) => is_ns_name(&format!("nvme{}", rest)),
+        None => false,
+    }
+}
+
+/* NSID from NVME_IOCTL_ID when block device node is accessible, then
+ * `nsid` sysfs attribute of `sysfs_path`.
+ */
+fn ns_nsid_get(root: &SystemRoot, dev_name: &str, sysfs_path: &str)
+    -> Option<u32> {
+    if let Some(nsid) = dev_node_find(root, SYSFS_BLOCK_FOLDER, dev_name)
+        .and_then(|p| nvme_ioctl_fd_open(&p).ok())
+        .and_then(|fd| nvme_ioctl_id(&fd).ok()) {
+        return Some(nsid);
+    }
+    sysfs_read_str(&format!("{}/nsid", sysfs_path)).ok()
+        .and_then(|nsid| nsid.parse::<u32>().ok())
+}
+
+/* Device node of kernel device `dev_name`. When udev renamed the node,
+ * the `/dev/block/<major:minor>` or `/dev/char/<major:minor>` link is used.
+ */
+fn dev_node_find(root: &SystemRoot, sysfs_class: &str, dev_name: &str)
+    -> Option<String> {
+    let dev_path = root.dev_path(dev_name);
+    if Path::new(&dev_path).exists() {
+        return Some(dev_path);
+    }
+    let dev_num = sysfs_read_str(&root.sysfs_path(
+        &format!("{}/{}/dev", sysfs_class, dev_name))).ok()?;
+    let dev_type = if sysfs_class == SYSFS_BLOCK_FOLDER {
+        "block"
+    } else {
+        "char"
+    };
+    canonicalize(root.dev_path(&format!("{}/{}", dev_type, dev_num))).ok()
+        .map(|p| p.to_string_lossy().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::subsystem::NvmeSubsystem;
+    use std::fs::{create_dir_all, remove_dir_all, write};
+    use std::os::unix::fs::symlink;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static TREE_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+    /* Fake sysfs and devfs tree removed on drop. Device nodes are regular
+     * files, so NVMe ioctls fail and sysfs attributes are used instead.
+     */
+    struct FakeTree {
+        path:                   String,
+    }
+
+    impl FakeTree {
+        fn new() -> FakeTree {
+            let path = format!(
+                "{}/nvme-discovery-test-{}-{}",
+                std::env::temp_dir().display(), std::process::id(),
+                TREE_COUNT.fetch_add(1, Ordering::SeqCst));
+            let tree = FakeTree { path };
+            tree.dir("sys/class/nvme/nvme-fabrics");
+            /* Controller nvme0: private path nvme0c0n1 of multipath head
+             * nvme0n1, private namespace nvme0n2 and namespace 3 with
+             * generic character device only.
+             */
+            tree.file("sys/class/nvme/nvme0/dev", "240:0");
+            tree.dir("sys/class/nvme/nvme0/power");
+            tree.file("sys/class/nvme/nvme0/nvme0c0n1/nsid", "1");
+            tree.dir("sys/class/nvme/nvme0/ng0n1");
+            tree.file("sys/class/nvme/nvme0/nvme0n2/nsid", "2");
+            tree.dir("sys/class/nvme/nvme0/ng0n2");
+            tree.dir("sys/class/nvme/nvme0/ng0n3");
+            /* Controller nvme1 renamed by udev to /dev/nvme-ctrl-b. Its
+             * instance differs from subsystem nvme-subsys0, so generic
+             * device of path nvme0c1n1 is ng1n1. Namespace 4 has generic
+             * character device only.
+             */
+            tree.file("sys/class/nvme/nvme1/dev", "240:1");
+            tree.file("sys/class/nvme/nvme1/nvme0c1n1/nsid", "1");
+            tree.dir("sys/class/nvme/nvme1/ng1n1");
+            tree.dir("sys/class/nvme/nvme1/ng1n4");
+            /* Controller nvme2 without device node */
+            tree.file("sys/class/nvme/nvme2/dev", "240:2");
+            for name in &["ng0n1", "ng0n2", "ng0n3", "ng1n1", "ng1n4"] {
+                tree.dir(&format!("sys/class/nvme-generic/{}", name));
+            }
+            for name in &["nvme0n1", "nvme0n2"] {
+                tree.dir(&format!("sys/class/block/{}", name));
+            }
+            tree.dir("sys/block/nvme0n1/multipath/nvme0c0n1");
+            tree.dir("sys/block/nvme0n1/multipath/nvme0c1n1");
+            tree.file("sys/class/nvme-subsystem/nvme-subsys0/subsysnqn",
+                      "nqn.2014-08.org.example:subsys0");
+            tree.file("sys/class/nvme-subsystem/nvme-subsys0/iopolicy",
+                      "numa");
+            tree.file("sys/module/nvme_core/parameters/multipath", "Y");
+            for name in &["nvme0", "nvme1", "nvme0n1", "power"] {
+                tree.dir(&format!("sys/class/nvme-subsystem/nvme-subsys0/{}",
+                                  name));
+            }
+            for name in &["nvme0", "nvme-ctrl-b", "nvme0n1", "nvme0n2",
+                          "ng0n1", "ng0n2", "ng0n3", "ng1n1", "ng1n4"] {
+                tree.file(&format!("dev/{}", name), "");
+            }
+            tree.dir("dev/char");
+            symlink(format!("{}/dev/nvme-ctrl-b", tree.path),
+                    format!("{}/dev/char/240:1", tree.path)).unwrap();
+            tree
+        }
+
+        fn dir(&self, sub_path: &str) {
+            create_dir_all(format!("{}/{}", self.path, sub_path)).unwrap();
+        }
+
+        fn file(&self, sub_path: &str, content: &str) {
+            let path = format!("{}/{}", self.path, sub_path);
+            create_dir_all(Path::new(&path).parent().unwrap()).unwrap();
+            write(path, format!("{}\n", content)).unwrap();
+        }
+
+        fn root(&self) -> SystemRoot {
+            SystemRoot::new(&format!("{}/sys", self.path),
+                            &format!("{}/dev", self.path))
+        }
+
+        fn dev(&self, name: &str) -> String {
+            format!("{}/dev/{}", self.path, name)
+        }
+    }
+
+    impl Drop for FakeTree {
+        fn drop(&mut self) {
+            let _ = remove_dir_all(&self.path);
+        }
+    }
+
+    #[test]
+    fn test_names() {
+        assert!(is_ctrl_name("nvme0"));
+        assert!(is_ctrl_name("nvme12"));
+        assert!(!is_ctrl_name("nvme"));
+        assert!(!is_ctrl_name("nvme-fabrics"));
+        assert!(!is_ctrl_name("nvme0n1"));
+        assert!(is_ns_name("nvme0n1"));
+        assert!(!is_ns_name("nvme0c1n1"));
+        assert!(!is_ns_name("nvme0n"));
+        assert!(!is_ns_name("nvme0n1p1"));
+        assert_eq!(path_head_name("nvme0c1n1").as_deref(), Some("nvme0n1"));
+        assert_eq!(path_head_name("nvme10c12n3").as_deref(),
+                   Some("nvme10n3"));
+        assert_eq!(path_head_name("nvme0n1"), None);
+        assert_eq!(path_head_name("nvme0cn1"), None);
+        assert_eq!(generic_name("nvme1n2"), "ng1n2");
+        assert!(is_generic_name("ng1n2"));
+        assert!(!is_generic_name("ng1"));
+        assert_eq!(ns_instance_get("nvme0c1n12"), Some("12"));
+        assert_eq!(ns_instance_get("ng1n12"), ns_instance_get("nvme0n12"));
+    }
+
+    #[test]
+    fn test_ctrl_devs() {
+        let tree = FakeTree::new();
+        let ctrls = NvmeCtrlDev::get_all_with_root(&tree.root());
+        let names: Vec<&str> = ctrls.iter().map(|c| c.name_get()).collect();
+        assert_eq!(names, vec!["nvme0", "nvme1"]);
+        assert_eq!(ctrls[0].dev_path_get(), tree.dev("nvme0"));
+        assert_eq!(ctrls[1].dev_path_get(), tree.dev("nvme-ctrl-b"));
+    }
+
+    #[test]
+    fn test_ns_devs() {
+        let tree = FakeTree::new();
+        let root = tree.root();
+        let ns_devs = NvmeNsDev::ctrl_ns_devs_get(&root, "nvme0").unwrap();
+        let names: Vec<&str> =
+            ns_devs.iter().map(|n| n.name_get()).collect();
+        assert_eq!(names, vec!["nvme0n1", "nvme0n2", "ng0n3"]);
+
+        assert_eq!(ns_devs[0].nsid_get(), Some(1));
+        assert_eq!(ns_devs[0].dev_paths_get().blk_path_get(),
+                   Some(tree.dev("nvme0n1").as_str()));
+        assert_eq!(ns_devs[0].dev_paths_get().generic_path_get(),
+                   Some(tree.dev("ng0n1").as_str()));
+        assert_eq!(ns_devs[0].path_names_get(),
+                   &["nvme0c0n1".to_string(), "nvme0c1n1".to_string()]);
+
+        assert_eq!(ns_devs[1].nsid_get(), Some(2));
+        assert_eq!(ns_devs[1].dev_paths_get().generic_path_get(),
+                   Some(tree.dev("ng0n2").as_str()));
+        assert!(ns_devs[1].path_names_get().is_empty());
+
+        assert_eq!(ns_devs[2].nsid_get(), None);
+        assert_eq!(ns_devs[2].dev_paths_get().blk_path_get(), None);
+        assert_eq!(ns_devs[2].dev_paths_get().generic_path_get(),
+                   Some(tree.dev("ng0n3").as_str()));
+    }
+
+    #[test]
+    fn test_ns_devs_ctrl_instance() {
+        let tree = FakeTree::new();
+        let ns_devs = NvmeNsDev::ctrl_ns_devs_get(&tree.root(), "nvme1")
+            .unwrap();
+        let names: Vec<&str> =
+            ns_devs.iter().map(|n| n.name_get()).collect();
+        assert_eq!(names, vec!["nvme0n1", "ng1n4"]);
+        assert_eq!(ns_devs[0].nsid_get(), Some(1));
+        assert_eq!(ns_devs[0].dev_paths_get().generic_path_get(),
+                   Some(tree.dev("ng0n1").as_str()));
+        assert_eq!(ns_devs[1].dev_paths_get().generic_path_get(),
+                   Some(tree.dev("ng1n4").as_str()));
+    }
+
+    #[test]
+    fn test_ns_resolve() {
+        let tree = FakeTree::new();
+        let root = tree.root();
+        let ns_dev = NvmeNsDev::resolve(&root, "nvme1", 1).unwrap();
+        assert_eq!(ns_dev.name_get(), "nvme0n1");
+        assert_eq!(NvmeNsDevPaths::resolve(&root, "nvme0", 2).unwrap()
+                   .blk_path_get(), Some(tree.dev("nvme0n2").as_str()));
+        match NvmeNsDevPaths::resolve(&root, "nvme1", 2) {
+            Err(NvmeError{kind: ErrorKind::NotFound(_)}) => (),
+            other => panic!("Expecting NotFound, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_subsystems() {
+        let tree = FakeTree::new();
+        let subsystems = NvmeSubsystem::get_all_with_root(&tree.root())
+            .unwrap();
+        assert_eq!(subsystems.len(), 1);
+        let subsys = &subsystems[0];
+        assert_eq!(subsys.name_get(), "nvme-subsys0");
+        assert_eq!(subsys.nqn_get(), "nqn.2014-08.org.example:subsys0");
+        assert_eq!(subsys.ctrl_names_get(),
+                   &["nvme0".to_string(), "nvme1".to_string()]);
+        assert_eq!(subsys.ns_blk_paths_get(), vec![tree.dev("nvme0n1")]);
+        assert!(subsys.is_multipath());
+
+        /* iopolicy still exists with multipath disabled */
+        tree.file("sys/module/nvme_core/parameters/multipath", "N");
+        let subsys = NvmeSubsystem::from_name_with_root("nvme-subsys0",
+                                                        &tree.root())
+            .unwrap();
+        assert_eq!(subsys.iopolicy_get(), "numa");
+        assert!(!subsys.is_multipath());
+    }
+}
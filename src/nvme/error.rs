@@ -18,12 +18,21 @@
 use std::result;
 use std::fmt;
 
+use super::passthru::NvmeCompletion;
+
 #[derive(Debug)]
 pub enum ErrorKind {
     LibBug(String),
     InvalidArgument(String),
     PermissionDenied(String),
     CorruptedData(String),
+    CommandFailed(String),
+    /// NVMe command completed with non-zero status, the completion keeps
+    /// status field and command specific dword 0.
+    NvmeStatus(NvmeCompletion, String),
+    NotSupported(String),
+    NotFound(String),
+    TimedOut(String),
 }
 
 #[derive(Debug)]
@@ -47,6 +56,11 @@ impl fmt::Display for NvmeError {
             ErrorKind::InvalidArgument(ref x) => x,
             ErrorKind::PermissionDenied(ref x) => x,
             ErrorKind::CorruptedData(ref x) => x,
+            ErrorKind::CommandFailed(ref x) => x,
+            ErrorKind::NvmeStatus(_, ref x) => x,
+            ErrorKind::NotSupported(ref x) => x,
+            ErrorKind::NotFound(ref x) => x,
+            ErrorKind::TimedOut(ref x) => x,
         })
     }
 }
@@ -60,6 +74,13 @@ impl ::std::error::Error for NvmeError {
             ErrorKind::PermissionDenied(_) => "Permission denied",
             ErrorKind::CorruptedData(_) =>
                 "Corrupted data from NVMe controller",
+            ErrorKind::CommandFailed(_) =>
+                "NVMe command failed",
+            ErrorKind::NvmeStatus(_, _) =>
+                "NVMe command completed with error status",
+            ErrorKind::NotSupported(_) => "Not supported",
+            ErrorKind::NotFound(_) => "Not found",
+            ErrorKind::TimedOut(_) => "Timed out",
         }
     }
 }
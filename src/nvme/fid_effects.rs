@@ -0,0 +1,155 @@
+/*
+ * Copyright (C) 2017 Red Hat, Inc.
+ * This library is free software; you can redistribute it and/or
+ * modify it under the terms of the GNU Lesser General Public
+ * License as published by the Free Software Foundation; either
+ * version 2.1 of the License, or (at your option) any later version.
+ *
+ * This library is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+ * Lesser General Public License for more details.
+ *
+ * You should have received a copy of the GNU Lesser General Public
+ * License along with this library; If not, see <http://www.gnu.org/licenses/>.
+ *
+ * Author: Gris Ge <fge@redhat.com>
+ */
+
+use super::error::*;
+use super::utils::to_u32;
+
+/* 256 dwords of FID Supported and Effects data structure, then reserved */
+pub(crate) const NVME_FID_EFFECTS_LOG_LEN: usize = 4096;
+const NVME_FID_COUNT: usize = 256;
+
+const NVME_FID_EFFECTS_FSUPP: u32 = 1 << 0;
+const NVME_FID_EFFECTS_UDCC: u32 = 1 << 1;
+const NVME_FID_EFFECTS_NCC: u32 = 1 << 2;
+const NVME_FID_EFFECTS_NIC: u32 = 1 << 3;
+const NVME_FID_EFFECTS_CCC: u32 = 1 << 4;
+const NVME_FID_EFFECTS_USS: u32 = 1 << 19;
+const NVME_FID_EFFECTS_FSP_SHIFT: u32 = 20;
+
+/// FID Supported and Effects data structure of a single feature.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct NvmeFidEffects {
+    raw:                        u32,
+}
+
+impl NvmeFidEffects {
+    pub fn raw_get(&self) -> u32 { self.raw }
+
+    /// FID Supported (FSUPP).
+    pub fn is_supported(&self) -> bool {
+        self.raw & NVME_FID_EFFECTS_FSUPP != 0
+    }
+
+    /// User Data Content Change (UDCC).
+    pub fn udcc(&self) -> bool { self.raw & NVME_FID_EFFECTS_UDCC != 0 }
+
+    /// Namespace Capability Change (NCC).
+    pub fn ncc(&self) -> bool { self.raw & NVME_FID_EFFECTS_NCC != 0 }
+
+    /// Namespace Inventory Change (NIC).
+    pub fn nic(&self) -> bool { self.raw & NVME_FID_EFFECTS_NIC != 0 }
+
+    /// Controller Capability Change (CCC).
+    pub fn ccc(&self) -> bool { self.raw & NVME_FID_EFFECTS_CCC != 0 }
+
+    /// UUID Selection Supported (USS).
+    pub fn uuid_sel_supported(&self) -> bool {
+        self.raw & NVME_FID_EFFECTS_USS != 0
+    }
+
+    /// Feature Identifier Scope (FSP) bit mask, e.g. bit 0 for namespace
+    /// scope, bit 1 for controller scope.
+    pub fn fsp_get(&self) -> u16 {
+        (self.raw >> NVME_FID_EFFECTS_FSP_SHIFT) as u16
+    }
+}
+
+/// Feature Identifiers Supported and Effects log page (12h).
+#[derive(Debug, Clone)]
+pub struct NvmeFidEffectsLog {
+    effects:                    Vec<NvmeFidEffects>,
+}
+
+impl NvmeFidEffectsLog {
+    pub fn fid_effects_get(&self, fid: u8) -> NvmeFidEffects {
+        self.effects[fid as usize]
+    }
+
+    /// Identifiers of all supported features.
+    pub fn fids_get(&self) -> Vec<u8> {
+        self.effects.iter().enumerate()
+            .filter(|(_, e)| e.is_supported())
+            .map(|(fid, _)| fid as u8)
+            .collect()
+    }
+
+    pub(crate) fn parse(data: &[u8]) -> Result<NvmeFidEffectsLog> {
+        if data.len() < NVME_FID_COUNT * 4 {
+            return Err(ErrorKind::CorruptedData(
+                "FID Supported and Effects log page is truncated"
+                .to_string()).into());
+        }
+        Ok(NvmeFidEffectsLog {
+            effects:            data[..NVME_FID_COUNT * 4].chunks(4)
+                .map(|c| NvmeFidEffects {
+                    raw:        to_u32([c[0], c[1], c[2], c[3]]),
+                })
+                .collect(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn effects_set(data: &mut [u8], fid: u8, raw: u32) {
+        let off = fid as usize * 4;
+        data[off..off + 4].copy_from_slice(&raw.to_le_bytes());
+    }
+
+    #[test]
+    fn test_parse() {
+        let mut data = vec![0u8; NVME_FID_EFFECTS_LOG_LEN];
+        effects_set(&mut data, 0x02, NVME_FID_EFFECTS_FSUPP |
+                    NVME_FID_EFFECTS_CCC | 0x2 << NVME_FID_EFFECTS_FSP_SHIFT);
+        effects_set(&mut data, 0xc0,
+                    NVME_FID_EFFECTS_FSUPP | NVME_FID_EFFECTS_UDCC |
+                    NVME_FID_EFFECTS_NCC | NVME_FID_EFFECTS_NIC |
+                    NVME_FID_EFFECTS_USS | 0x1 << NVME_FID_EFFECTS_FSP_SHIFT);
+        let log = NvmeFidEffectsLog::parse(&data).unwrap();
+
+        assert_eq!(log.fids_get(), vec![0x02, 0xc0]);
+        let power = log.fid_effects_get(0x02);
+        assert!(power.is_supported());
+        assert!(power.ccc());
+        assert!(!power.udcc());
+        assert_eq!(power.fsp_get(), 0x2);
+
+        let vendor = log.fid_effects_get(0xc0);
+        assert!(vendor.udcc() && vendor.ncc() && vendor.nic() &&
+                vendor.uuid_sel_supported());
+        assert!(!vendor.ccc());
+        assert_eq!(vendor.fsp_get(), 0x1);
+        assert!(!log.fid_effects_get(0xff).is_supported());
+    }
+
+    #[test]
+    fn test_parse_empty() {
+        let log = NvmeFidEffectsLog::parse(
+            &vec![0u8; NVME_FID_EFFECTS_LOG_LEN]).unwrap();
+        assert!(log.fids_get().is_empty());
+    }
+
+    #[test]
+    fn test_parse_truncated() {
+        for len in &[0, 4, NVME_FID_COUNT * 4 - 1] {
+            assert!(NvmeFidEffectsLog::parse(&vec![0xffu8; *len]).is_err());
+        }
+    }
+}
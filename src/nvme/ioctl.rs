@@ -18,15 +18,28 @@
 
 use std::fs::File;
 use std::fs::OpenOptions;
+use std::io;
+use std::ops::Deref;
 use std::os::unix::io::AsRawFd;
+use std::slice;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
 
+use super::cmd_opts::{NvmeCmdOpts, NvmeCmdOptsTable};
 use super::error::*;
+use super::passthru::NvmeCompletion;
+use super::utils::mutex_lock;
 
+pub(crate) const NVME_IOC_CMD_GET_LOG_PAGE: u8      = 0x02;
 pub(crate) const NVME_IOC_CMD_IDENTIFY: u8          = 0x06;
+pub(crate) const NVME_IOC_CMD_SET_FEATURES: u8      = 0x09;
+pub(crate) const NVME_IOC_CMD_GET_FEATURES: u8      = 0x0a;
+
+pub(crate) const NVME_IDENTIFY_DATA_LEN: usize      = 4096;
 
 
 #[repr(C, packed)]
-#[derive(Default)]
+#[derive(Default, Clone, Copy)]
 // Copy from /usr/include/linux/nvme_ioctl.h
 pub struct NvmeAdminCmd {
     pub opcode:         u8,
@@ -50,19 +63,284 @@ pub struct NvmeAdminCmd {
 }
 
 const NVME_IOC_MAGIC: u8 = b'N';
+const NVME_IOC_ID: u8 = 0x40;
 const NVME_IOC_ADMIN_CMD: u8 = 0x41;
+const NVME_IOC_IO_CMD: u8 = 0x43;
+const NVME_IOC_RESET: u8 = 0x44;
+const NVME_IOC_SUBSYS_RESET: u8 = 0x45;
+const NVME_IOC_RESCAN: u8 = 0x46;
 
 ioctl!(readwrite_buf _nvme_ioctl_admin_cmd with
        NVME_IOC_MAGIC, NVME_IOC_ADMIN_CMD;
        NvmeAdminCmd);
+ioctl!(readwrite_buf _nvme_ioctl_io_cmd with
+       NVME_IOC_MAGIC, NVME_IOC_IO_CMD;
+       NvmeAdminCmd);
+ioctl!(none _nvme_ioctl_id with NVME_IOC_MAGIC, NVME_IOC_ID);
+ioctl!(none _nvme_ioctl_reset with NVME_IOC_MAGIC, NVME_IOC_RESET);
+ioctl!(none _nvme_ioctl_subsys_reset with
+       NVME_IOC_MAGIC, NVME_IOC_SUBSYS_RESET);
+ioctl!(none _nvme_ioctl_rescan with NVME_IOC_MAGIC, NVME_IOC_RESCAN);
+
+/* File descriptor shared by controller and its namespaces across threads.
+ * Single ioctl is atomic in kernel, the `cmd_lock` is only for command
+ * sequences which should not interleave with others on the same handle,
+ * e.g. iterating a list or log page in several commands.
+ */
+pub(crate) struct NvmeDevFd {
+    file:                       File,
+    cmd_lock:                   Mutex<()>,
+    opts:                       Arc<NvmeCmdOptsTable>,
+}
+
+impl NvmeDevFd {
+    pub(crate) fn new(file: File) -> NvmeDevFd {
+        NvmeDevFd::with_opts(file, Arc::new(NvmeCmdOptsTable::default()))
+    }
+
+    /* Namespace device shares command options of its controller */
+    pub(crate) fn with_opts(file: File, opts: Arc<NvmeCmdOptsTable>)
+        -> NvmeDevFd {
+        NvmeDevFd {
+            file,
+            cmd_lock:           Mutex::new(()),
+            opts,
+        }
+    }
+
+    pub(crate) fn opts_get(&self) -> &Arc<NvmeCmdOptsTable> {
+        &self.opts
+    }
+
+    pub(crate) fn lock(&self) -> MutexGuard<'_, ()> {
+        mutex_lock(&self.cmd_lock)
+    }
+}
+
+impl Deref for NvmeDevFd {
+    type Target = File;
+    fn deref(&self) -> &File {
+        &self.file
+    }
+}
 
 pub(crate) fn nvme_ioctl_fd_open(blk_path: &str) -> Result<File> {
     Ok(OpenOptions::new().read(true).open(blk_path)?)
 }
 
-pub(crate) fn nvme_ioctl_admin_cmd(fd: &File,
-                                   nvme_cmd: NvmeAdminCmd) -> Result<()> {
-    let fd_raw: i32 = AsRawFd::as_raw_fd(fd);
-    unsafe {_nvme_ioctl_admin_cmd(fd_raw, &mut [nvme_cmd])?;}
+/* Return the command specific result (completion queue entry dword 0),
+ * non-zero NVMe status is treated as error.
+ */
+pub(crate) fn nvme_ioctl_admin_cmd(fd: &NvmeDevFd,
+                                   nvme_cmd: NvmeAdminCmd) -> Result<u32> {
+    let opcode = nvme_cmd.opcode;
+    nvme_ioctl_cmd_run(fd, true, nvme_cmd)?.result_get(true, opcode)
+}
+
+/* Return the completion even when NVMe status is not zero */
+pub(crate) fn nvme_ioctl_admin_passthru(fd: &NvmeDevFd,
+                                        nvme_cmd: NvmeAdminCmd)
+    -> Result<NvmeCompletion> {
+    nvme_ioctl_cmd_run(fd, true, nvme_cmd)
+}
+
+/* I/O command on namespace block or generic character device, the
+ * command layout is identical to admin command. Return the completion
+ * even when NVMe status is not zero.
+ */
+pub(crate) fn nvme_ioctl_io_passthru(fd: &NvmeDevFd,
+                                     nvme_cmd: NvmeAdminCmd)
+    -> Result<NvmeCompletion> {
+    nvme_ioctl_cmd_run(fd, false, nvme_cmd)
+}
+
+/* Apply timeout and retry policy of command options. Timeout specified in
+ * `nvme_cmd` takes precedence. Status of the last attempt is returned.
+ */
+fn nvme_ioctl_cmd_run(fd: &NvmeDevFd, is_admin: bool,
+                      nvme_cmd: NvmeAdminCmd) -> Result<NvmeCompletion> {
+    let opts = fd.opts.get(is_admin, nvme_cmd.opcode);
+    nvme_ioctl_cmd_run_opts(fd, is_admin, nvme_cmd, &opts)
+}
+
+fn nvme_ioctl_cmd_run_opts(fd: &NvmeDevFd, is_admin: bool,
+                           mut nvme_cmd: NvmeAdminCmd, opts: &NvmeCmdOpts)
+    -> Result<NvmeCompletion> {
+    let fd_raw: i32 = AsRawFd::as_raw_fd(&fd.file);
+    if nvme_cmd.timeout_ms == 0 {
+        nvme_cmd.timeout_ms = opts.timeout_ms();
+    }
+    let opcode = nvme_cmd.opcode;
+    let timeout_ms = nvme_cmd.timeout_ms;
+    let mut attempt = 0;
+    loop {
+        let mut cmd = nvme_cmd;
+        let rc = unsafe {
+            if is_admin {
+                _nvme_ioctl_admin_cmd(fd_raw, slice::from_mut(&mut cmd))
+            } else {
+                _nvme_ioctl_io_cmd(fd_raw, slice::from_mut(&mut cmd))
+            }
+        };
+        let status = match rc {
+            Ok(s) => s,
+            Err(::nix::Error::Sys(errno)) => return Err(
+                nvme_cmd_errno_error(errno as i32, opcode, timeout_ms)),
+            Err(e) => return Err(e.into()),
+        };
+        /* Positive return value of ioctl is the NVMe status field */
+        let completion = NvmeCompletion {
+            result:             cmd.result,
+            status:             status as u16,
+        };
+        if status == 0 {
+            return Ok(completion);
+        }
+        match fd.opts.retry_delay(opts, completion.status, attempt) {
+            Some(delay) => thread::sleep(delay),
+            None => return Ok(completion),
+        }
+        attempt += 1;
+    }
+}
+
+/* Error of NVMe passthrough command failed by kernel with `errno`, shared
+ * by ioctl and io_uring. Kernel fails the command with EINTR when
+ * cancelling it after `timeout_ms` expired.
+ */
+pub(crate) fn nvme_cmd_errno_error(errno: i32, opcode: u8, timeout_ms: u32)
+    -> NvmeError {
+    match errno {
+        libc::EINTR | libc::ETIMEDOUT if timeout_ms != 0 =>
+            ErrorKind::TimedOut(format!(
+                "NVMe command 0x{:02x} cancelled by kernel after {} ms",
+                opcode, timeout_ms)),
+        libc::EACCES | libc::EPERM => ErrorKind::PermissionDenied(format!(
+            "NVMe command 0x{:02x} refused by kernel: {}", opcode,
+            io::Error::from_raw_os_error(errno))),
+        _ => ErrorKind::CommandFailed(format!(
+            "NVMe command 0x{:02x} failed: {}", opcode,
+            io::Error::from_raw_os_error(errno))),
+    }.into()
+}
+
+/* NSID of namespace block or generic character device */
+pub(crate) fn nvme_ioctl_id(fd: &File) -> Result<u32> {
+    let nsid = unsafe { _nvme_ioctl_id(AsRawFd::as_raw_fd(fd))? };
+    Ok(nsid as u32)
+}
+
+pub(crate) fn nvme_ioctl_reset(fd: &File) -> Result<()> {
+    unsafe { _nvme_ioctl_reset(AsRawFd::as_raw_fd(fd))? };
+    Ok(())
+}
+
+pub(crate) fn nvme_ioctl_subsys_reset(fd: &File) -> Result<()> {
+    unsafe { _nvme_ioctl_subsys_reset(AsRawFd::as_raw_fd(fd))? };
+    Ok(())
+}
+
+pub(crate) fn nvme_ioctl_rescan(fd: &File) -> Result<()> {
+    unsafe { _nvme_ioctl_rescan(AsRawFd::as_raw_fd(fd))? };
+    Ok(())
+}
+
+pub(crate) fn nvme_ioctl_identify(fd: &NvmeDevFd, cns: u8, nsid: u32,
+                                  cntid: u16, csi: u8, data: &mut [u8])
+    -> Result<()> {
+    let nvme_cmd = NvmeAdminCmd {
+        opcode:             NVME_IOC_CMD_IDENTIFY,
+        addr:               data.as_mut_ptr() as u64,
+        data_len:           data.len() as u32,
+        cdw10:              (u32::from(cntid) << 16) | u32::from(cns),
+        cdw11:              u32::from(csi) << 24,
+        nsid,
+        ..                  Default::default()
+    };
+    nvme_ioctl_admin_cmd(fd, nvme_cmd)?;
     Ok(())
 }
+
+/* Fields of Get Log Page command other than offset and data buffer */
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct NvmeGetLogArgs {
+    pub(crate) lid:             u8,
+    pub(crate) nsid:            u32,
+    pub(crate) lsp:             u8,
+    pub(crate) lsi:             u16,
+    pub(crate) csi:             u8,
+    pub(crate) rae:             bool,
+    // Read with side effect, e.g. telemetry Create bit, must not retry.
+    pub(crate) no_retry:        bool,
+}
+
+/* Single Get Log Page command, `data` length should be non-zero multiple
+ * of 4.
+ */
+pub(crate) fn nvme_ioctl_get_log(fd: &NvmeDevFd, args: &NvmeGetLogArgs,
+                                 offset: u64, data: &mut [u8])
+    -> Result<()> {
+    let numd = (data.len() / 4) as u32 - 1;
+    let nvme_cmd = NvmeAdminCmd {
+        opcode:             NVME_IOC_CMD_GET_LOG_PAGE,
+        addr:               data.as_mut_ptr() as u64,
+        data_len:           data.len() as u32,
+        cdw10:              ((numd & 0xffff) << 16) |
+                            if args.rae { 1 << 15 } else { 0 } |
+                            (u32::from(args.lsp & 0x7f) << 8) |
+                            u32::from(args.lid),
+        cdw11:              (u32::from(args.lsi) << 16) | (numd >> 16),
+        cdw12:              offset as u32,
+        cdw13:              (offset >> 32) as u32,
+        cdw14:              u32::from(args.csi) << 24,
+        nsid:               args.nsid,
+        ..                  Default::default()
+    };
+    let mut opts = fd.opts.get(true, NVME_IOC_CMD_GET_LOG_PAGE);
+    if args.no_retry {
+        opts.retries = 0;
+    }
+    nvme_ioctl_cmd_run_opts(fd, true, nvme_cmd, &opts)?
+        .result_get(true, NVME_IOC_CMD_GET_LOG_PAGE)?;
+    Ok(())
+}
+
+pub(crate) fn nvme_ioctl_get_feature(fd: &NvmeDevFd, fid: u8, nsid: u32,
+                                     cdw11: u32) -> Result<u32> {
+    let nvme_cmd = NvmeAdminCmd {
+        opcode:             NVME_IOC_CMD_GET_FEATURES,
+        cdw10:              u32::from(fid),
+        cdw11,
+        nsid,
+        ..                  Default::default()
+    };
+    nvme_ioctl_admin_cmd(fd, nvme_cmd)
+}
+
+/* Get Features command with data structure returned in `data` */
+pub(crate) fn nvme_ioctl_get_feature_data(fd: &NvmeDevFd, fid: u8,
+                                          nsid: u32, cdw11: u32,
+                                          data: &mut [u8]) -> Result<u32> {
+    let nvme_cmd = NvmeAdminCmd {
+        opcode:             NVME_IOC_CMD_GET_FEATURES,
+        addr:               data.as_mut_ptr() as u64,
+        data_len:           data.len() as u32,
+        cdw10:              u32::from(fid),
+        cdw11,
+        nsid,
+        ..                  Default::default()
+    };
+    nvme_ioctl_admin_cmd(fd, nvme_cmd)
+}
+
+pub(crate) fn nvme_ioctl_set_feature(fd: &NvmeDevFd, fid: u8, nsid: u32,
+                                     cdw11: u32, save: bool) -> Result<u32> {
+    let nvme_cmd = NvmeAdminCmd {
+        opcode:             NVME_IOC_CMD_SET_FEATURES,
+        cdw10:              u32::from(fid) | if save { 1 << 31 } else { 0 },
+        cdw11,
+        nsid,
+        ..                  Default::default()
+    };
+    nvme_ioctl_admin_cmd(fd, nvme_cmd)
+}
@@ -20,16 +20,75 @@
 extern crate nix;
 extern crate byteorder;
 extern crate libc;
-extern crate regex;
+#[cfg(feature = "async")]
+extern crate tokio;
 
-pub use self::controller::{NvmeController};
+#[cfg(feature = "async")]
+pub use self::async_ctrl::NvmeAsyncController;
+pub use self::cmd_effects::{NvmeCmdEffects, NvmeCmdEffectsLog,
+                            NvmeCmdSubmitExec};
+pub use self::cmd_opts::NvmeCmdOpts;
+pub use self::command_set::NvmeCommandSet;
+pub use self::ctrl_attr::{NvmeCtrlState, NvmeTransport};
+pub use self::controller::{NvmeController, NvmeScanFailure, NvmeScanResult};
+pub use self::fid_effects::{NvmeFidEffects, NvmeFidEffectsLog};
+pub use self::multipath::{NvmeAnaGroup, NvmeAnaLog, NvmeAnaState, NvmePath};
 pub use self::namespace::NvmeNameSpace;
+pub use self::discovery::{NvmeCtrlDev, NvmeNsDev};
+pub use self::ns_dev::NvmeNsDevPaths;
+pub use self::ns_id_desc::NvmeNsIdDesc;
+pub use self::passthru::{NvmeCmd, NvmeCmdTransport, NvmeCompletion,
+                         NvmeDataDirection, NvmeOpcodePolicy};
+pub use self::pci::NvmePciInfo;
+pub use self::persistent_event::{NvmePersistentEvent,
+                                 NvmePersistentEventData,
+                                 NvmePersistentEventHeader,
+                                 NvmePersistentEventLog,
+                                 NvmePowerOnResetInfo};
+pub use self::root::SystemRoot;
+pub use self::subsystem::NvmeSubsystem;
+pub use self::supported_log::{NvmeLogPageSupport, NvmeSupportedLogPages};
+pub use self::telemetry::{NvmeTelemetryHeader, NvmeTelemetryReason,
+                          NvmeTelemetryType};
+pub use self::temperature::NvmeTemperature;
+#[cfg(feature = "io_uring")]
+pub use self::uring::{NvmeUringRequest, NvmeUringTransport};
 pub use self::error::{NvmeError, ErrorKind};
 pub use self::utils::size_bytes_2_size_human;
 
+#[cfg(feature = "async")]
+mod async_ctrl;
+mod cmd_effects;
+mod cmd_opts;
+mod command_set;
 mod error;
 mod controller;
+mod ctrl_attr;
+mod discovery;
+mod fid_effects;
 mod ioctl;
+mod multipath;
 mod namespace;
+mod ns_dev;
+mod ns_id_desc;
+mod passthru;
+mod pci;
+mod persistent_event;
+mod root;
 mod utils;
 mod spec;
+mod subsystem;
+mod supported_log;
+mod telemetry;
+mod temperature;
+#[cfg(feature = "io_uring")]
+mod uring;
+
+/* Compile time check: handles are shared among threads */
+#[allow(dead_code)]
+fn _send_sync_check() {
+    fn is_send_sync<T: Send + Sync>() {}
+    is_send_sync::<NvmeController>();
+    is_send_sync::<NvmeNameSpace>();
+    is_send_sync::<NvmeSubsystem>();
+}
@@ -0,0 +1,309 @@
+/*
+ * Copyright (C) 2017 Red Hat, Inc.
+ * This library is free software; you can redistribute it and/or
+ * modify it under the terms of the GNU Lesser General Public
+ * License as published by the Free Software Foundation; either
+ * version 2.1 of the License, or (at your option) any later version.
+ *
+ * This library is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+ * Lesser General Public License for more details.
+ *
+ * You should have received a copy of the GNU Lesser General Public
+ * License along with this library; If not, see <http://www.gnu.org/licenses/>.
+ *
+ * Author: Gris Ge <fge@redhat.com>
+ */
+
+use std::fmt;
+use std::fs::read_link;
+use std::path::Path;
+
+use super::ctrl_attr::NvmeTransport;
+use super::error::*;
+use super::root::SystemRoot;
+use super::utils::{dir_entry_names_get, sysfs_read_str, to_u16, to_u32,
+                   to_u64};
+
+/* ANA log page header: change count(8), group count(2), reserved(6) */
+pub(crate) const NVME_ANA_LOG_HEADER_LEN: usize = 16;
+/* ANA group descriptor without NSID list */
+pub(crate) const NVME_ANA_GROUP_DESC_LEN: usize = 32;
+
+/// Asymmetric Namespace Access state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NvmeAnaState {
+    Optimized,
+    NonOptimized,
+    Inaccessible,
+    PersistentLoss,
+    Change,
+    Unknown(u8),
+}
+
+impl NvmeAnaState {
+    pub(crate) fn from_raw(state: u8) -> NvmeAnaState {
+        match state & 0xf {
+            0x1 => NvmeAnaState::Optimized,
+            0x2 => NvmeAnaState::NonOptimized,
+            0x3 => NvmeAnaState::Inaccessible,
+            0x4 => NvmeAnaState::PersistentLoss,
+            0xf => NvmeAnaState::Change,
+            s => NvmeAnaState::Unknown(s),
+        }
+    }
+
+    /* Parse `ana_state` sysfs attribute of linux kernel */
+    pub(crate) fn from_sysfs(state: &str) -> NvmeAnaState {
+        match state {
+            "optimized" => NvmeAnaState::Optimized,
+            "non-optimized" => NvmeAnaState::NonOptimized,
+            "inaccessible" => NvmeAnaState::Inaccessible,
+            "persistent-loss" => NvmeAnaState::PersistentLoss,
+            "change" => NvmeAnaState::Change,
+            _ => NvmeAnaState::Unknown(0),
+        }
+    }
+
+    /// Whether I/O could be sent via path in this state.
+    pub fn is_accessible(&self) -> bool {
+        matches!(*self,
+                 NvmeAnaState::Optimized | NvmeAnaState::NonOptimized)
+    }
+}
+
+impl fmt::Display for NvmeAnaState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            NvmeAnaState::Optimized => write!(f, "optimized"),
+            NvmeAnaState::NonOptimized => write!(f, "non-optimized"),
+            NvmeAnaState::Inaccessible => write!(f, "inaccessible"),
+            NvmeAnaState::PersistentLoss => write!(f, "persistent-loss"),
+            NvmeAnaState::Change => write!(f, "change"),
+            NvmeAnaState::Unknown(s) => write!(f, "unknown(0x{:x})", s),
+        }
+    }
+}
+
+/// A path of native NVMe multipath namespace, linux kernel name it as
+/// hidden block device `nvmeXcYnZ`.
+#[derive(Debug, Clone)]
+pub struct NvmePath {
+    name:                       String,
+    ctrl_name:                  String,
+    transport:                  Option<NvmeTransport>,
+    ana_state:                  Option<NvmeAnaState>,
+    ana_grpid:                  Option<u32>,
+}
+
+impl NvmePath {
+    /// Kernel name of path, e.g. `nvme0c1n1`.
+    pub fn name_get(&self) -> &str { &self.name }
+    /// Kernel name of controller of this path, e.g. `nvme1`.
+    pub fn ctrl_name_get(&self) -> &str { &self.ctrl_name }
+    /// None if controller is removed.
+    pub fn transport_get(&self) -> Option<&NvmeTransport> {
+        self.transport.as_ref()
+    }
+    /// None if controller does not support ANA reporting.
+    pub fn ana_state_get(&self) -> Option<NvmeAnaState> { self.ana_state }
+    /// None if controller does not support ANA reporting.
+    pub fn ana_grpid_get(&self) -> Option<u32> { self.ana_grpid }
+
+    /* Paths of multipath head `ns_name`, empty if not multipath */
+    pub(crate) fn paths_get(root: &SystemRoot, ns_name: &str)
+        -> Result<Vec<NvmePath>> {
+        let mut ret = Vec::new();
+        let mpath_dir = root.sysfs_path(
+            &format!("block/{}/multipath", ns_name));
+        if !Path::new(&mpath_dir).is_dir() {
+            return Ok(ret);
+        }
+        for path_name in dir_entry_names_get(&mpath_dir)? {
+            ret.push(NvmePath::from_name(root, &path_name)?);
+        }
+        Ok(ret)
+    }
+
+    fn from_name(root: &SystemRoot, path_name: &str) -> Result<NvmePath> {
+        let sysfs_path = root.sysfs_path(&format!("block/{}", path_name));
+        let ctrl_name = match read_link(format!("{}/device", sysfs_path)) {
+            Ok(p) => p.file_name().and_then(|n| n.to_str())
+                .unwrap_or("").to_string(),
+            Err(_) => ctrl_name_of_path(path_name),
+        };
+        let transport = sysfs_read_str(
+            &format!("{}/transport", root.ctrl_sysfs_path(&ctrl_name)))
+            .ok().map(|t| NvmeTransport::from_sysfs(&t));
+        let ana_state = sysfs_read_str(&format!("{}/ana_state", sysfs_path))
+            .ok().map(|s| NvmeAnaState::from_sysfs(&s));
+        let ana_grpid = sysfs_read_str(&format!("{}/ana_grpid", sysfs_path))
+            .ok().and_then(|s| s.parse::<u32>().ok());
+        Ok(NvmePath {
+            name:               path_name.to_string(),
+            ctrl_name,
+            transport,
+            ana_state,
+            ana_grpid,
+        })
+    }
+}
+
+/* Path name is `nvme<subsys>c<ctrl>n<nsid>` */
+fn ctrl_name_of_path(path_name: &str) -> String {
+    match path_name.find('c') {
+        Some(i) => {
+            let rest = &path_name[i + 1..];
+            let end = rest.find('n').unwrap_or(rest.len());
+            format!("nvme{}", &rest[..end])
+        },
+        None => String::new(),
+    }
+}
+
+/// ANA Group Descriptor of ANA log page.
+#[derive(Debug, Clone)]
+pub struct NvmeAnaGroup {
+    pub grpid:                  u32,
+    pub change_count:           u64,
+    pub state:                  NvmeAnaState,
+    pub nsids:                  Vec<u32>,
+}
+
+/// Asymmetric Namespace Access log page (0Ch).
+#[derive(Debug, Clone)]
+pub struct NvmeAnaLog {
+    pub change_count:           u64,
+    pub groups:                 Vec<NvmeAnaGroup>,
+}
+
+impl NvmeAnaLog {
+    pub(crate) fn parse(data: &[u8]) -> Result<NvmeAnaLog> {
+        if data.len() < NVME_ANA_LOG_HEADER_LEN {
+            return Err(ErrorKind::CorruptedData(
+                "ANA log page is shorter than its header".to_string()).into());
+        }
+        let mut u64_bytes = [0u8; 8];
+        u64_bytes.copy_from_slice(&data[0..8]);
+        let change_count = to_u64(u64_bytes);
+        let group_count = to_u16([data[8], data[9]]);
+        let mut groups = Vec::with_capacity(group_count as usize);
+        let mut offset = NVME_ANA_LOG_HEADER_LEN;
+        for _ in 0..group_count {
+            if offset + NVME_ANA_GROUP_DESC_LEN > data.len() {
+                return Err(ErrorKind::CorruptedData(format!(
+                    "ANA group descriptor at offset {} exceeds the log \
+                     page length", offset)).into());
+            }
+            let desc = &data[offset..offset + NVME_ANA_GROUP_DESC_LEN];
+            let grpid = to_u32([desc[0], desc[1], desc[2], desc[3]]);
+            let nsid_count = to_u32([desc[4], desc[5], desc[6], desc[7]])
+                as usize;
+            u64_bytes.copy_from_slice(&desc[8..16]);
+            let group_change_count = to_u64(u64_bytes);
+            let state = NvmeAnaState::from_raw(desc[16]);
+            offset += NVME_ANA_GROUP_DESC_LEN;
+            if offset + nsid_count * 4 > data.len() {
+                return Err(ErrorKind::CorruptedData(format!(
+                    "NSID list of ANA group {} exceeds the log page length",
+                    grpid)).into());
+            }
+            let nsids = data[offset..offset + nsid_count * 4].chunks(4)
+                .map(|c| to_u32([c[0], c[1], c[2], c[3]]))
+                .collect();
+            offset += nsid_count * 4;
+            groups.push(NvmeAnaGroup {
+                grpid,
+                change_count:   group_change_count,
+                state,
+                nsids,
+            });
+        }
+        Ok(NvmeAnaLog {
+            change_count,
+            groups,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ana_header_build(change_count: u64, group_count: u16) -> Vec<u8> {
+        let mut data = vec![0u8; NVME_ANA_LOG_HEADER_LEN];
+        data[0..8].copy_from_slice(&change_count.to_le_bytes());
+        data[8..10].copy_from_slice(&group_count.to_le_bytes());
+        data
+    }
+
+    fn ana_group_push(data: &mut Vec<u8>, grpid: u32, change_count: u64,
+                      state: u8, nsids: &[u32]) {
+        let mut desc = [0u8; NVME_ANA_GROUP_DESC_LEN];
+        desc[0..4].copy_from_slice(&grpid.to_le_bytes());
+        desc[4..8].copy_from_slice(&(nsids.len() as u32).to_le_bytes());
+        desc[8..16].copy_from_slice(&change_count.to_le_bytes());
+        desc[16] = state;
+        data.extend_from_slice(&desc);
+        for nsid in nsids {
+            data.extend_from_slice(&nsid.to_le_bytes());
+        }
+    }
+
+    #[test]
+    fn test_ana_log_parse() {
+        let mut data = ana_header_build(0x10, 3);
+        ana_group_push(&mut data, 1, 5, 0x01, &[1, 2]);
+        ana_group_push(&mut data, 2, 6, 0x03, &[]);
+        /* Reserved upper bits of state are ignored */
+        ana_group_push(&mut data, 3, 7, 0xf2, &[0xffff_fffe]);
+        let log = NvmeAnaLog::parse(&data).unwrap();
+
+        assert_eq!(log.change_count, 0x10);
+        assert_eq!(log.groups.len(), 3);
+        assert_eq!(log.groups[0].grpid, 1);
+        assert_eq!(log.groups[0].change_count, 5);
+        assert_eq!(log.groups[0].state, NvmeAnaState::Optimized);
+        assert_eq!(log.groups[0].nsids, vec![1, 2]);
+        assert_eq!(log.groups[1].state, NvmeAnaState::Inaccessible);
+        assert!(log.groups[1].nsids.is_empty());
+        assert_eq!(log.groups[2].state, NvmeAnaState::NonOptimized);
+        assert_eq!(log.groups[2].nsids, vec![0xffff_fffe]);
+    }
+
+    #[test]
+    fn test_ana_log_parse_empty() {
+        let log = NvmeAnaLog::parse(&ana_header_build(1, 0)).unwrap();
+        assert_eq!(log.change_count, 1);
+        assert!(log.groups.is_empty());
+    }
+
+    #[test]
+    fn test_ana_log_parse_truncated() {
+        assert!(NvmeAnaLog::parse(&[0u8; NVME_ANA_LOG_HEADER_LEN - 1])
+                .is_err());
+        /* More groups claimed than present */
+        let mut data = ana_header_build(1, 2);
+        ana_group_push(&mut data, 1, 1, 0x01, &[1]);
+        assert!(NvmeAnaLog::parse(&data).is_err());
+        /* NSID list cut short */
+        let mut data = ana_header_build(1, 1);
+        ana_group_push(&mut data, 1, 1, 0x01, &[1, 2]);
+        data.truncate(data.len() - 1);
+        assert!(NvmeAnaLog::parse(&data).is_err());
+    }
+
+    #[test]
+    fn test_ana_state() {
+        assert_eq!(NvmeAnaState::from_raw(0x0f), NvmeAnaState::Change);
+        assert_eq!(NvmeAnaState::from_raw(0x04),
+                   NvmeAnaState::PersistentLoss);
+        assert_eq!(NvmeAnaState::from_raw(0x05), NvmeAnaState::Unknown(5));
+        assert_eq!(NvmeAnaState::from_sysfs("non-optimized"),
+                   NvmeAnaState::NonOptimized);
+        assert!(NvmeAnaState::Optimized.is_accessible());
+        assert!(!NvmeAnaState::Change.is_accessible());
+        assert_eq!(ctrl_name_of_path("nvme0c12n1"), "nvme12");
+    }
+}
@@ -17,29 +17,48 @@
  */
 use std::mem::size_of;
 use std::str;
-use regex::Regex;
-use std::fs::OpenOptions;
-use std::io::prelude::Read;
-use std::fs::read_dir;
+use std::sync::{Arc, Mutex};
 
+use super::command_set::*;
 use super::ioctl::*;
+use super::discovery::{is_ns_name, NvmeNsDev};
+use super::ns_id_desc::*;
+use super::passthru::*;
+use super::multipath::NvmePath;
+use super::root::SystemRoot;
 use super::spec::*;
 use super::error::*;
 use super::utils::*;
 use super::NvmeController;
+#[cfg(feature = "io_uring")]
+use super::uring::NvmeUringTransport;
 
-const NVME_ADMIN_CMD_CNS_ALL_ACTIVE_NS: u32 = 0x02;
+const NVME_ADMIN_CMD_CNS_ALL_ACTIVE_NS: u8 = 0x02;
 const NVME_ADMIN_CMD_CNS_IDENTIFY_NS: u32 = 0x00;
-const IDT_NS_MAX_COUNT: usize = 1024;
-/* maximum 1024 ns id returned per identify command */
+const NVME_ADMIN_CMD_CNS_NS_ID_DESC: u8 = 0x03;
+const NVME_ADMIN_CMD_CNS_CSI_NS: u8 = 0x05;
+const NVME_ADMIN_CMD_CNS_CSI_ACTIVE_NS: u8 = 0x07;
+const NVME_ADMIN_CMD_CNS_ALLOCATED_NS_LIST: u8 = 0x10;
+const NVME_ADMIN_CMD_CNS_ALLOCATED_NS: u32 = 0x11;
+const NVME_ADMIN_CMD_CNS_CSI_ALLOCATED_NS_LIST: u8 = 0x1a;
 
 pub struct NvmeNameSpace {
     raw_id_data:                NvmeSpecIdNsData,
     blk_path:                   String,
+    generic_path:               String,
+    // Kernel name, e.g. `nvme0n1` even if udev renamed the device node.
+    name:                       String,
     ctrl_blk_path:              String,
+    ctrl_fd:                    Arc<NvmeDevFd>,
+    // Namespace device node opened on first I/O command.
+    dev_fd:                     Mutex<Option<Arc<NvmeDevFd>>>,
     nsid:                       u32,
     eui64:                      String,
     nguid:                      String,
+    csi:                        NvmeCommandSet,
+    wwid_fallback:              String,
+    attached:                   bool,
+    root:                       SystemRoot,
 }
 
 impl NvmeNameSpace {
@@ -68,154 +87,325 @@ impl NvmeNameSpace {
         to_u64(self.raw_id_data.nsze) * lba_size
     }
 
+    /// Block device, e.g. `/dev/nvme0n1`. Empty if kernel cannot expose
+    /// the namespace as block device (e.g. unsupported LBA format or
+    /// command set), use `generic_path_get()` then.
     pub fn blk_path_get(&self) -> &str {
         &self.blk_path
     }
 
-    pub(crate) fn new(ctrl_blk_path: &str, _ctrl: &NvmeController,
-                      nsid: u32) -> Result<NvmeNameSpace> {
-        let fd = nvme_ioctl_fd_open(ctrl_blk_path)?;
+    /// Generic character device, e.g. `/dev/ng0n1`. Empty if kernel does
+    /// not support it or namespace is detached.
+    pub fn generic_path_get(&self) -> &str {
+        &self.generic_path
+    }
+
+    /// Whether this namespace is attached to the controller. Detached
+    /// namespace is only returned by
+    /// `NvmeController::allocated_namespaces_get()` and has no block device.
+    pub fn is_attached(&self) -> bool {
+        self.attached
+    }
+
+    /// Issue I/O command `opcode` via the generic character device, or
+    /// block device if kernel has no generic character device support.
+    /// `cdws` are command dword 10 to 15. Data is transferred from or to
+    /// `data` as the direction encoded in the opcode. Same checks as
+    /// `io_passthru()` are applied. NVMe status is returned in the
+    /// completion instead of error.
+    pub fn io_cmd(&self, opcode: u8, cdws: [u32; 6], data: &mut [u8])
+        -> Result<NvmeCompletion> {
+        let cmd = NvmeCmd {
+            opcode,
+            cdw10:              cdws[0],
+            cdw11:              cdws[1],
+            cdw12:              cdws[2],
+            cdw13:              cdws[3],
+            cdw14:              cdws[4],
+            cdw15:              cdws[5],
+            ..                  Default::default()
+        };
+        /* Bidirectional opcode is refused by io_passthru() */
+        let dir = NvmeDataDirection::from_opcode(opcode)
+            .unwrap_or(NvmeDataDirection::HostToController);
+        self.io_passthru(&cmd, dir, data, None)
+    }
+
+    /// Open io_uring transport of `depth` entries on generic character
+    /// device of this namespace for batched I/O passthrough, commands are
+    /// checked against `NvmeController::opcode_policy_get()`.
+    #[cfg(feature = "io_uring")]
+    pub fn uring_transport_open(&self, depth: u32)
+        -> Result<NvmeUringTransport> {
+        if self.generic_path.is_empty() {
+            return Err(ErrorKind::NotSupported(format!(
+                "Namespace {} of controller {} has no generic character \
+                 device", self.nsid, self.ctrl_blk_path)).into());
+        }
+        NvmeUringTransport::open_with_opts(&self.generic_path, depth,
+                                           self.ctrl_fd.opts_get().clone())
+    }
+
+    /// Issue I/O command via the device node of this namespace, e.g. vendor
+    /// specific opcode. The `cmd.nsid` could be zero for the NSID of this
+    /// namespace. The `dir` should match the transfer direction encoded in
+    /// opcode, `metadata` is the separate metadata buffer for namespace
+    /// formatted with separate metadata. Command denied by
+    /// `NvmeController::opcode_policy_set()` fails with
+    /// `ErrorKind::PermissionDenied`. NVMe status is returned in the
+    /// completion instead of error.
+    pub fn io_passthru(&self, cmd: &NvmeCmd, dir: NvmeDataDirection,
+                       buffer: &mut [u8], metadata: Option<&mut [u8]>)
+        -> Result<NvmeCompletion> {
+        let mut cmd = *cmd;
+        if cmd.nsid == 0 {
+            cmd.nsid = self.nsid;
+        } else if cmd.nsid != self.nsid {
+            return Err(ErrorKind::InvalidArgument(format!(
+                "NSID {} does not match namespace {}", cmd.nsid, self.nsid))
+                .into());
+        }
+        let mut no_metadata = [0u8; 0];
+        let metadata = metadata.unwrap_or(&mut no_metadata);
+        passthru_check(&self.ctrl_fd.opts_get().policy_get(), false, &cmd,
+                       dir, buffer.len(), metadata.len())?;
+        let fd = self.dev_fd_get()?;
+        nvme_ioctl_io_passthru(&fd, cmd.to_passthru_with_metadata(buffer,
+                                                                  metadata))
+    }
+
+    /* Generic character device works for namespaces kernel cannot expose
+     * as block device, hence preferred.
+     */
+    fn dev_fd_get(&self) -> Result<Arc<NvmeDevFd>> {
+        let mut dev_fd = mutex_lock(&self.dev_fd);
+        if let Some(ref fd) = *dev_fd {
+            return Ok(fd.clone());
+        }
+        let dev_path = if !self.generic_path.is_empty() {
+            &self.generic_path
+        } else if !self.blk_path.is_empty() {
+            &self.blk_path
+        } else {
+            return Err(ErrorKind::NotFound(format!(
+                "Namespace {} of controller {} has no device node",
+                self.nsid, self.ctrl_blk_path)).into());
+        };
+        let fd = Arc::new(NvmeDevFd::with_opts(
+            nvme_ioctl_fd_open(dev_path)?, self.ctrl_fd.opts_get().clone()));
+        *dev_fd = Some(fd.clone());
+        Ok(fd)
+    }
+
+    /// Paths of native NVMe multipath namespace with ANA state of each.
+    /// Empty if this namespace is not a multipath head.
+    pub fn paths(&self) -> Result<Vec<NvmePath>> {
+        if !is_ns_name(&self.name) {
+            return Ok(Vec::new());
+        }
+        NvmePath::paths_get(&self.root, &self.name)
+    }
+
+    /// The I/O Command Set this namespace is associated with.
+    pub fn command_set(&self) -> NvmeCommandSet {
+        self.csi
+    }
+
+    /// Query the I/O Command Set specific Identify Namespace data
+    /// structure (CNS 05h) of this namespace. The layout of returned 4096
+    /// bytes depends on `command_set()`.
+    pub fn id_ns_csi_get(&self) -> Result<Vec<u8>> {
+        let mut data = vec![0u8; NVME_IDENTIFY_DATA_LEN];
+        nvme_ioctl_identify(&self.ctrl_fd, NVME_ADMIN_CMD_CNS_CSI_NS,
+                            self.nsid, 0, self.csi.csi(), &mut data)?;
+        Ok(data)
+    }
+
+    /// Query the Namespace Identification Descriptor list (CNS 03h).
+    /// Controllers prior to NVMe 1.3 do not support this command.
+    pub fn id_descriptors(&self) -> Result<Vec<NvmeNsIdDesc>> {
+        let mut data = vec![0u8; NVME_IDENTIFY_DATA_LEN];
+        nvme_ioctl_identify(&self.ctrl_fd, NVME_ADMIN_CMD_CNS_NS_ID_DESC,
+                            self.nsid, 0, NVME_CSI_NVM, &mut data)?;
+        ns_id_descs_parse(&data)
+    }
+
+    /// World wide identifier of this namespace, identical to what linux
+    /// kernel shows in `/sys/block/nvmeXnY/wwid`: UUID, then NGUID, then
+    /// EUI64, then a combination of PCI vendor ID, serial number, model
+    /// number and NSID.
+    pub fn wwid(&self) -> String {
+        let mut uuid = String::new();
+        let mut nguid = self.nguid.clone();
+        let mut eui64 = self.eui64.clone();
+        /* Failure is expected on controllers prior to NVMe 1.3, the
+         * identifiers in Identify Namespace data structure are used then.
+         */
+        if let Ok(descs) = self.id_descriptors() {
+            for desc in descs {
+                match desc {
+                    NvmeNsIdDesc::Uuid(u) => uuid = u,
+                    NvmeNsIdDesc::Nguid(n) => {
+                        if !n.is_empty() {
+                            nguid = n;
+                        }
+                    },
+                    NvmeNsIdDesc::Eui64(e) => {
+                        if !e.is_empty() {
+                            eui64 = e;
+                        }
+                    },
+                    _ => continue,
+                }
+            }
+        }
+        if !uuid.is_empty() {
+            format!("uuid.{}", uuid)
+        } else if !nguid.is_empty() {
+            format!("eui.{}", nguid)
+        } else if !eui64.is_empty() {
+            format!("eui.{}", eui64)
+        } else {
+            self.wwid_fallback.clone()
+        }
+    }
+
+    /// Query NSID from namespace block device or generic character device,
+    /// e.g. `/dev/nvme0n1`.
+    pub fn nsid_from_device(blk_path: &str) -> Result<u32> {
+        nvme_ioctl_id(&nvme_ioctl_fd_open(blk_path)?)
+    }
+
+    /* `ns_dev` is None for detached namespace or namespace without device
+     * node, callers resolve all namespaces of controller in one sysfs scan.
+     */
+    pub(crate) fn new(ctrl: &NvmeController, nsid: u32,
+                      csi: NvmeCommandSet, attached: bool,
+                      ns_dev: Option<NvmeNsDev>) -> Result<NvmeNameSpace> {
+        let fd = ctrl.fd_share()?;
+        let ctrl_blk_path = ctrl.blk_path_get();
 
         let mut id_data: NvmeSpecIdNsData = Default::default();
 
+        /* Inactive namespace returns all zero in Identify Namespace data
+         * structure, detached namespace has to use CNS 11h.
+         */
+        let cns = if attached {
+            NVME_ADMIN_CMD_CNS_IDENTIFY_NS
+        } else {
+            NVME_ADMIN_CMD_CNS_ALLOCATED_NS
+        };
+
         let nvme_cmd = NvmeAdminCmd {
             opcode:             NVME_IOC_CMD_IDENTIFY,
             addr:               &mut id_data as *mut NvmeSpecIdNsData as u64,
             data_len:           size_of::<NvmeSpecIdNsData>() as u32,
-            cdw10:              NVME_ADMIN_CMD_CNS_IDENTIFY_NS,
-            nsid:               nsid,
+            cdw10:              cns,
+            nsid,
             ..                  Default::default()
         };
 
         nvme_ioctl_admin_cmd(&fd, nvme_cmd)?;
 
-        let blk_path = match get_blk_path(ctrl_blk_path, nsid) {
-            Some(b) => b,
-            None => String::new(),
-        };
+        let dev_paths = ns_dev.as_ref().map(|n| n.dev_paths_get().clone())
+            .unwrap_or_default();
 
         Ok(NvmeNameSpace{
-            //BUG(Gris Ge): blk_path here should be blk_path of namspace.
-            ctrl_blk_path:      format!("{}", ctrl_blk_path),
-            nsid:               nsid,
-            blk_path:           blk_path,
+            ctrl_blk_path:      ctrl_blk_path.to_string(),
+            ctrl_fd:            fd,
+            dev_fd:             Mutex::new(None),
+            nsid,
+            blk_path:           dev_paths.blk_path_get().unwrap_or("")
+                                    .to_string(),
+            generic_path:       dev_paths.generic_path_get().unwrap_or("")
+                                    .to_string(),
+            name:               ns_dev.map(|n| n.name_get().to_string())
+                                    .unwrap_or_default(),
             eui64:              to_hex_string(&id_data.eui64),
             nguid:              to_hex_string(&id_data.nguid),
             raw_id_data:        id_data,
+            csi,
+            wwid_fallback:      format!("{}-{:08x}", ctrl.wwid_prefix_get(),
+                                        nsid),
+            attached,
+            root:               ctrl.root_get().clone(),
         })
     }
 
-    pub(crate) fn nsid_list_get(blk_path: &str,
-                                 ctrl: &NvmeController) -> Result<Vec<u32>> {
+    /* Query namespace ID list via specified CNS, only NSID greater than
+     * `cur_nsid` is returned by controller, hence we loop till got a
+     * non-full list.
+     */
+    fn nsid_list_query(ctrl: &NvmeController, cns: u8,
+                       csi: NvmeCommandSet) -> Result<Vec<u32>> {
         let mut ret = Vec::new();
-        let nsid_count = ctrl.nn_get();
-
-        if ctrl.ver_get() < NvmeController::ver_gen(1, 1, 0) {
-            /* pre SPEC 1.1.0, Namespaces shall be allocated in
-             * order (starting with 1) and packed sequentially.
-             */
-            for nsid in 1..nsid_count + 1 {
-                ret.push(nsid);
-            }
-            return Ok(ret);
-        }
-        /* Query all active ns list */
-        let fd = nvme_ioctl_fd_open(blk_path)?;
-
+        let fd = ctrl.fd_get()?;
+        let _lock = fd.lock();
         let mut cur_nsid: u32 = 0;
 
-        while (ret.len() as u32) < nsid_count {
-            let mut nsid_list_data = [[0u8; 4]; IDT_NS_MAX_COUNT];
-            let nvme_cmd = NvmeAdminCmd {
-                opcode:             NVME_IOC_CMD_IDENTIFY,
-                addr:               &mut nsid_list_data
-                                        as *mut [[u8; 4]; IDT_NS_MAX_COUNT]
-                                        as u64,
-                data_len:           size_of::<[[u8; 4]; IDT_NS_MAX_COUNT]>()
-                                        as u32,
-                cdw10:              NVME_ADMIN_CMD_CNS_ALL_ACTIVE_NS,
-                nsid:              cur_nsid,
-                ..                  Default::default()
-            };
-
-            nvme_ioctl_admin_cmd(&fd, nvme_cmd)?;
-            for nsid_array in nsid_list_data.iter() {
-                let nsid = to_u32(nsid_array.clone());
+        loop {
+            let mut nsid_list_data = vec![0u8; NVME_IDENTIFY_DATA_LEN];
+            nvme_ioctl_identify(fd, cns, cur_nsid, 0, csi.csi(),
+                                &mut nsid_list_data)?;
+            let mut got_full_list = true;
+            for chunk in nsid_list_data.chunks(4) {
+                let nsid = to_u32([chunk[0], chunk[1], chunk[2], chunk[3]]);
                 if nsid == 0 {
+                    got_full_list = false;
                     break;
-                } else {
-                    ret.push(nsid);
-                    cur_nsid = nsid;
                 }
+                ret.push(nsid);
+                cur_nsid = nsid;
+            }
+            /* NSID FFFFFFFFh is broadcast, cannot query beyond it */
+            if !got_full_list || cur_nsid >= 0xfffffffe {
+                break;
             }
         }
-
         Ok(ret)
     }
-}
 
-fn get_nsid_from_sysfs(ctrl_name: &str, ns_name: &str) -> u32 {
-    let sysfs_path = format!("/sys/class/nvme/{}/{}/nsid",
-                             ctrl_name, ns_name);
-
-    let mut fd = match OpenOptions::new().read(true).open(sysfs_path) {
-        Err(_) => return 0u32,
-        Ok(i) => i,
-    };
-
-    let mut contents = String::new();
-    match fd.read_to_string(&mut contents) {
-        Err(_) => return 0u32,
-        Ok(_) => ()
-    }
-    /* Remove trailing '\n' */
-    contents.trim().trim();
-    let re = Regex::new(r"^([0-9]+)\n$").unwrap();
-    let nsid = match re.captures(&contents) {
-        Some(caps) => format!("{}", &caps[1]),
-        None => return 0u32,
-    };
-
-    nsid.parse::<u32>().unwrap()
-}
+    /* Query active namespace list associated with specified I/O Command
+     * Set (CNS 07h).
+     */
+    pub(crate) fn nsid_list_csi_get(ctrl: &NvmeController,
+                                    csi: NvmeCommandSet) -> Result<Vec<u32>> {
+        NvmeNameSpace::nsid_list_query(ctrl,
+                                       NVME_ADMIN_CMD_CNS_CSI_ACTIVE_NS, csi)
+    }
 
-fn get_blk_path(ctrl_blk_path: &str, nsid: u32) -> Option<String> {
-    let re = Regex::new(r"^/dev/(nvme[0-9]+)$").unwrap();
-    let ctrl_name = match re.captures(ctrl_blk_path) {
-        Some(caps) => format!("{}", &caps[1]),
-        None => return None
-    };
-    /* In best chance linux kernel just match nsid to /dev/nvme1nX.
-     * We try first before doing deep look up.
+    /* Query allocated namespace list (CNS 10h), which include namespaces
+     * not attached to any controller.
      */
-    let ns_name = &format!("{}n{}", ctrl_name, nsid);
-    if get_nsid_from_sysfs(&ctrl_name, &ns_name) == nsid {
-        return Some(format!("/dev/{}", ns_name));
-    }
-
-    /* Have to do the hard way by search all */
-    let sysfs_path = format!("/sys/class/nvme/{}/", ctrl_name);
-    let re = Regex::new(r"^(nvme[0-9]+n[0-9]+)$").unwrap();
-    match read_dir(&sysfs_path) {
-        Err(_) => return None,
-        Ok(paths) => for path in paths {
-            match path {
-                /* Got error when interate, it might happen when
-                 * nvme controler got removed after we open that dir
-                 */
-                Err(_) => continue,
-                Ok(dir_entry) => {
-                    let ns_name = dir_entry.file_name()
-                        .into_string().unwrap();
-                    if re.is_match(&ns_name) &&
-                       get_nsid_from_sysfs(&ctrl_name, &ns_name) == nsid {
-                        return Some(format!("/dev/{}", ns_name));
-                    }
-                    continue;
-                }
-            };
-        }
-    };
+    pub(crate) fn allocated_nsid_list_get(ctrl: &NvmeController)
+        -> Result<Vec<u32>> {
+        NvmeNameSpace::nsid_list_query(ctrl,
+                                       NVME_ADMIN_CMD_CNS_ALLOCATED_NS_LIST,
+                                       NvmeCommandSet::Nvm)
+    }
+
+    /* Query allocated namespace list associated with specified I/O Command
+     * Set (CNS 1Ah).
+     */
+    pub(crate) fn allocated_nsid_list_csi_get(ctrl: &NvmeController,
+                                              csi: NvmeCommandSet)
+        -> Result<Vec<u32>> {
+        NvmeNameSpace::nsid_list_query(
+            ctrl, NVME_ADMIN_CMD_CNS_CSI_ALLOCATED_NS_LIST, csi)
+    }
 
-    None
+    pub(crate) fn nsid_list_get(ctrl: &NvmeController) -> Result<Vec<u32>> {
+        let nsid_count = ctrl.nn_get();
+
+        if ctrl.ver_get() < NvmeController::ver_gen(1, 1, 0) {
+            /* pre SPEC 1.1.0, Namespaces shall be allocated in
+             * order (starting with 1) and packed sequentially.
+             */
+            return Ok((1..nsid_count + 1).collect());
+        }
+        /* Query all active ns list */
+        NvmeNameSpace::nsid_list_query(ctrl,
+                                       NVME_ADMIN_CMD_CNS_ALL_ACTIVE_NS,
+                                       NvmeCommandSet::Nvm)
+    }
 }
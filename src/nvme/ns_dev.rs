@@ -0,0 +1,64 @@
+/*
+ * Copyright (C) 2017 Red Hat, Inc.
+ * This library is free software; you can redistribute it and/or
+ * modify it under the terms of the GNU Lesser General Public
+ * License as published by the Free Software Foundation; either
+ * version 2.1 of the License, or (at your option) any later version.
+ *
+ * This library is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+ * Lesser General Public License for more details.
+ *
+ * You should have received a copy of the GNU Lesser General Public
+ * License along with this library; If not, see <http://www.gnu.org/licenses/>.
+ *
+ * Author: Gris Ge <fge@redhat.com>
+ */
+
+use super::discovery::NvmeNsDev;
+use super::error::*;
+use super::root::SystemRoot;
+
+/// Device nodes of a namespace. Linux kernel 5.13+ creates generic
+/// character device `ngXnY` for every namespace, while block device
+/// `nvmeXnY` only exists when kernel supports the namespace format.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NvmeNsDevPaths {
+    blk_path:                   Option<String>,
+    generic_path:               Option<String>,
+}
+
+impl NvmeNsDevPaths {
+    /// Block device, e.g. `/dev/nvme0n1`. With native NVMe multipath, this
+    /// is the multipath head.
+    pub fn blk_path_get(&self) -> Option<&str> {
+        self.blk_path.as_deref()
+    }
+
+    /// Generic character device, e.g. `/dev/ng0n1`.
+    pub fn generic_path_get(&self) -> Option<&str> {
+        self.generic_path.as_deref()
+    }
+
+    pub(crate) fn new(blk_path: Option<String>,
+                      generic_path: Option<String>) -> NvmeNsDevPaths {
+        NvmeNsDevPaths {
+            blk_path,
+            generic_path,
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.blk_path.is_none() && self.generic_path.is_none()
+    }
+
+    /* Resolve device nodes of namespace `nsid` attached to controller of
+     * kernel name `ctrl_name`, ErrorKind::NotFound if the namespace has no
+     * device node.
+     */
+    pub(crate) fn resolve(root: &SystemRoot, ctrl_name: &str, nsid: u32)
+        -> Result<NvmeNsDevPaths> {
+        Ok(NvmeNsDev::resolve(root, ctrl_name, nsid)?.dev_paths_get().clone())
+    }
+}
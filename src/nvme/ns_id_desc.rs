@@ -0,0 +1,100 @@
+/*
+ * Copyright (C) 2017 Red Hat, Inc.
+ * This library is free software; you can redistribute it and/or
+ * modify it under the terms of the GNU Lesser General Public
+ * License as published by the Free Software Foundation; either
+ * version 2.1 of the License, or (at your option) any later version.
+ *
+ * This library is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+ * Lesser General Public License for more details.
+ *
+ * You should have received a copy of the GNU Lesser General Public
+ * License along with this library; If not, see <http://www.gnu.org/licenses/>.
+ *
+ * Author: Gris Ge <fge@redhat.com>
+ */
+
+use super::command_set::NvmeCommandSet;
+use super::error::*;
+use super::utils::to_hex_string;
+
+const NVME_NIDT_EUI64: u8                   = 0x01;
+const NVME_NIDT_NGUID: u8                   = 0x02;
+const NVME_NIDT_UUID: u8                    = 0x03;
+const NVME_NIDT_CSI: u8                     = 0x04;
+
+const NVME_NIDT_EUI64_LEN: usize            = 8;
+const NVME_NIDT_NGUID_LEN: usize            = 16;
+const NVME_NIDT_UUID_LEN: usize             = 16;
+const NVME_NIDT_CSI_LEN: usize              = 1;
+
+/* NIDT(1 byte), NIDL(1 byte) and 2 bytes reserved */
+const NVME_NID_HEADER_LEN: usize            = 4;
+
+/// Namespace Identification Descriptor returned by Identify CNS 03h.
+/// Identifiers are stored as lower case hex strings, UUID is formatted as
+/// `xxxxxxxx-xxxx-xxxx-xxxx-xxxxxxxxxxxx`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NvmeNsIdDesc {
+    Eui64(String),
+    Nguid(String),
+    Uuid(String),
+    Csi(NvmeCommandSet),
+    Unknown(u8, Vec<u8>),
+}
+
+pub(crate) fn uuid_to_string(data: &[u8]) -> String {
+    let hex = to_hex_string(data);
+    if hex.len() != NVME_NIDT_UUID_LEN * 2 {
+        return hex;
+    }
+    format!("{}-{}-{}-{}-{}", &hex[0..8], &hex[8..12], &hex[12..16],
+            &hex[16..20], &hex[20..32])
+}
+
+pub(crate) fn ns_id_descs_parse(data: &[u8]) -> Result<Vec<NvmeNsIdDesc>> {
+    let mut ret = Vec::new();
+    let mut offset = 0usize;
+    while offset + NVME_NID_HEADER_LEN <= data.len() {
+        let nidt = data[offset];
+        let nidl = data[offset + 1] as usize;
+        /* Descriptor list is terminated by zero NIDT */
+        if nidt == 0 {
+            break;
+        }
+        let start = offset + NVME_NID_HEADER_LEN;
+        let end = start + nidl;
+        if end > data.len() {
+            return Err(ErrorKind::CorruptedData(format!(
+                "Namespace Identification Descriptor type {} at offset {} \
+                 exceeds the data length", nidt, offset)).into());
+        }
+        let nid = &data[start..end];
+        let expected_len = match nidt {
+            NVME_NIDT_EUI64 => Some(NVME_NIDT_EUI64_LEN),
+            NVME_NIDT_NGUID => Some(NVME_NIDT_NGUID_LEN),
+            NVME_NIDT_UUID => Some(NVME_NIDT_UUID_LEN),
+            NVME_NIDT_CSI => Some(NVME_NIDT_CSI_LEN),
+            _ => None,
+        };
+        if let Some(l) = expected_len {
+            if l != nidl {
+                return Err(ErrorKind::CorruptedData(format!(
+                    "Namespace Identification Descriptor type {} has \
+                     invalid length {}, expecting {}", nidt, nidl, l)).into());
+            }
+        }
+        ret.push(match nidt {
+            NVME_NIDT_EUI64 => NvmeNsIdDesc::Eui64(to_hex_string(nid)),
+            NVME_NIDT_NGUID => NvmeNsIdDesc::Nguid(to_hex_string(nid)),
+            NVME_NIDT_UUID => NvmeNsIdDesc::Uuid(uuid_to_string(nid)),
+            NVME_NIDT_CSI =>
+                NvmeNsIdDesc::Csi(NvmeCommandSet::from_csi(nid[0])),
+            _ => NvmeNsIdDesc::Unknown(nidt, nid.to_vec()),
+        });
+        offset = end;
+    }
+    Ok(ret)
+}
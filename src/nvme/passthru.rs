@@ -0,0 +1,302 @@
+/*
+ * Copyright (C) 2017 Red Hat, Inc.
+ * This library is free software; you can redistribute it and/or
+ * modify it under the terms of the GNU Lesser General Public
+ * License as published by the Free Software Foundation; either
+ * version 2.1 of the License, or (at your option) any later version.
+ *
+ * This library is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+ * Lesser General Public License for more details.
+ *
+ * You should have received a copy of the GNU Lesser General Public
+ * License along with this library; If not, see <http://www.gnu.org/licenses/>.
+ *
+ * Author: Gris Ge <fge@redhat.com>
+ */
+
+use std::collections::HashSet;
+
+use super::error::*;
+use super::ioctl::NvmeAdminCmd;
+
+const NVME_STATUS_SC_MASK: u16 = 0xff;
+const NVME_STATUS_SCT_SHIFT: u16 = 8;
+const NVME_STATUS_SCT_MASK: u16 = 0x7;
+const NVME_STATUS_CRD_SHIFT: u16 = 11;
+const NVME_STATUS_CRD_MASK: u16 = 0x3;
+const NVME_STATUS_MORE: u16 = 1 << 13;
+const NVME_STATUS_DNR: u16 = 1 << 14;
+
+const NVME_SCT_GENERIC: u8 = 0x0;
+const NVME_SCT_CMD_SPECIFIC: u8 = 0x1;
+const NVME_SC_INVALID_OPCODE: u8 = 0x01;
+const NVME_SC_INVALID_FIELD: u8 = 0x02;
+const NVME_SC_INVALID_LOG_PAGE: u8 = 0x09;
+
+/* Data transfer direction is encoded in opcode bits 1:0 */
+const NVME_OPCODE_XFER_MASK: u8 = 0x3;
+const NVME_OPCODE_XFER_TO_CTRL: u8 = 0x1;
+const NVME_OPCODE_XFER_FROM_CTRL: u8 = 0x2;
+const NVME_OPCODE_XFER_BIDIRECTIONAL: u8 = 0x3;
+
+/// NVMe command without data buffer, the buffer is passed separately so
+/// that its length could be validated against the opcode.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct NvmeCmd {
+    pub opcode:                 u8,
+    pub flags:                  u8,
+    pub nsid:                   u32,
+    pub cdw2:                   u32,
+    pub cdw3:                   u32,
+    pub cdw10:                  u32,
+    pub cdw11:                  u32,
+    pub cdw12:                  u32,
+    pub cdw13:                  u32,
+    pub cdw14:                  u32,
+    pub cdw15:                  u32,
+    /// Zero means the timeout of `NvmeCmdOpts` of this opcode.
+    pub timeout_ms:             u32,
+}
+
+impl NvmeCmd {
+    /* Command layout of linux kernel `struct nvme_passthru_cmd` with
+     * metadata buffer.
+     */
+    pub(crate) fn to_passthru_with_metadata(self, data: &mut [u8],
+                                            metadata: &mut [u8])
+        -> NvmeAdminCmd {
+        NvmeAdminCmd {
+            metadata:           if metadata.is_empty() {
+                                    0
+                                } else {
+                                    metadata.as_mut_ptr() as u64
+                                },
+            metadata_len:       metadata.len() as u32,
+            ..                  self.to_passthru(data)
+        }
+    }
+
+    /* Command layout of linux kernel `struct nvme_passthru_cmd` */
+    pub(crate) fn to_passthru(self, data: &mut [u8]) -> NvmeAdminCmd {
+        NvmeAdminCmd {
+            opcode:             self.opcode,
+            flags:              self.flags,
+            nsid:               self.nsid,
+            cdw2:               self.cdw2,
+            cdw3:               self.cdw3,
+            addr:               if data.is_empty() {
+                                    0
+                                } else {
+                                    data.as_mut_ptr() as u64
+                                },
+            data_len:           data.len() as u32,
+            cdw10:              self.cdw10,
+            cdw11:              self.cdw11,
+            cdw12:              self.cdw12,
+            cdw13:              self.cdw13,
+            cdw14:              self.cdw14,
+            cdw15:              self.cdw15,
+            timeout_ms:         self.timeout_ms,
+            ..                  Default::default()
+        }
+    }
+}
+
+/// Completion of a NVMe command.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct NvmeCompletion {
+    /// Command specific result, completion queue entry dword 0.
+    pub result:                 u32,
+    /// Status field of completion queue entry without phase tag, zero on
+    /// success.
+    pub status:                 u16,
+}
+
+impl NvmeCompletion {
+    pub fn is_success(&self) -> bool {
+        self.status == 0
+    }
+
+    /// Status Code.
+    pub fn sc(&self) -> u8 {
+        (self.status & NVME_STATUS_SC_MASK) as u8
+    }
+
+    /// Status Code Type, e.g. 0 for generic, 1 for command specific.
+    pub fn sct(&self) -> u8 {
+        ((self.status >> NVME_STATUS_SCT_SHIFT) & NVME_STATUS_SCT_MASK) as u8
+    }
+
+    /// Command Retry Delay, index of CRDT in Identify Controller data
+    /// structure, 0 means no delay.
+    pub fn crd(&self) -> u8 {
+        ((self.status >> NVME_STATUS_CRD_SHIFT) & NVME_STATUS_CRD_MASK) as u8
+    }
+
+    /// More status information is available in Error Information log.
+    pub fn more(&self) -> bool {
+        self.status & NVME_STATUS_MORE != 0
+    }
+
+    /// Do Not Retry.
+    pub fn dnr(&self) -> bool {
+        self.status & NVME_STATUS_DNR != 0
+    }
+
+    /* Controller rejected the command or its parameters, e.g. unknown log
+     * page identifier, rather than failed to execute it.
+     */
+    pub(crate) fn is_invalid_cmd(&self) -> bool {
+        matches!((self.sct(), self.sc()),
+                 (NVME_SCT_GENERIC, NVME_SC_INVALID_OPCODE) |
+                 (NVME_SCT_GENERIC, NVME_SC_INVALID_FIELD) |
+                 (NVME_SCT_CMD_SPECIFIC, NVME_SC_INVALID_LOG_PAGE))
+    }
+
+    /* Command specific result, error if status is not zero */
+    pub(crate) fn result_get(&self, is_admin: bool, opcode: u8)
+        -> Result<u32> {
+        if self.is_success() {
+            Ok(self.result)
+        } else {
+            Err(ErrorKind::NvmeStatus(*self, format!(
+                "NVMe {} command 0x{:02x} failed with status 0x{:04x} \
+                 (SCT 0x{:x}, SC 0x{:02x})",
+                if is_admin { "admin" } else { "I/O" }, opcode, self.status,
+                self.sct(), self.sc())).into())
+        }
+    }
+}
+
+/// Data transfer direction of passthrough command, it should match the
+/// direction encoded in bits 1:0 of opcode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NvmeDataDirection {
+    None,
+    HostToController,
+    ControllerToHost,
+}
+
+impl NvmeDataDirection {
+    /* None if opcode is bidirectional, linux kernel does not support it */
+    pub(crate) fn from_opcode(opcode: u8) -> Option<NvmeDataDirection> {
+        match opcode & NVME_OPCODE_XFER_MASK {
+            NVME_OPCODE_XFER_TO_CTRL =>
+                Some(NvmeDataDirection::HostToController),
+            NVME_OPCODE_XFER_FROM_CTRL =>
+                Some(NvmeDataDirection::ControllerToHost),
+            NVME_OPCODE_XFER_BIDIRECTIONAL => None,
+            _ => Some(NvmeDataDirection::None),
+        }
+    }
+}
+
+/// Opcodes allowed for `NvmeController::admin_passthru()` and
+/// `NvmeNameSpace::io_passthru()`. Default allows all opcodes, once any
+/// opcode is added by `admin_allow()` or `io_allow()`, only listed
+/// opcodes of that command type are allowed.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NvmeOpcodePolicy {
+    admin_allowed:              Option<HashSet<u8>>,
+    io_allowed:                 Option<HashSet<u8>>,
+    unsupported_reject:         bool,
+}
+
+impl NvmeOpcodePolicy {
+    pub fn unsupported_reject_get(&self) -> bool {
+        self.unsupported_reject
+    }
+
+    /// Refuse admin command marked as not supported in Commands Supported
+    /// and Effects log page. No command is refused when controller does
+    /// not support that log page.
+    pub fn unsupported_reject_set(&mut self, reject: bool) {
+        self.unsupported_reject = reject;
+    }
+
+    pub fn admin_allow(&mut self, opcode: u8) {
+        self.admin_allowed.get_or_insert_with(HashSet::new).insert(opcode);
+    }
+
+    pub fn io_allow(&mut self, opcode: u8) {
+        self.io_allowed.get_or_insert_with(HashSet::new).insert(opcode);
+    }
+
+    pub fn is_admin_allowed(&self, opcode: u8) -> bool {
+        match self.admin_allowed {
+            Some(ref allowed) => allowed.contains(&opcode),
+            None => true,
+        }
+    }
+
+    pub fn is_io_allowed(&self, opcode: u8) -> bool {
+        match self.io_allowed {
+            Some(ref allowed) => allowed.contains(&opcode),
+            None => true,
+        }
+    }
+}
+
+/* Validate passthrough command against opcode policy, transfer direction
+ * and buffer lengths.
+ */
+pub(crate) fn passthru_check(policy: &NvmeOpcodePolicy, is_admin: bool,
+                             cmd: &NvmeCmd, dir: NvmeDataDirection,
+                             data_len: usize, metadata_len: usize)
+    -> Result<()> {
+    let cmd_type = if is_admin { "admin" } else { "I/O" };
+    let allowed = if is_admin {
+        policy.is_admin_allowed(cmd.opcode)
+    } else {
+        policy.is_io_allowed(cmd.opcode)
+    };
+    if !allowed {
+        return Err(ErrorKind::PermissionDenied(format!(
+            "NVMe {} opcode 0x{:02x} is not allowed by opcode policy",
+            cmd_type, cmd.opcode)).into());
+    }
+    match NvmeDataDirection::from_opcode(cmd.opcode) {
+        Some(d) if d == dir => (),
+        Some(d) => return Err(ErrorKind::InvalidArgument(format!(
+            "NVMe {} opcode 0x{:02x} transfers data {:?}, but {:?} \
+             requested", cmd_type, cmd.opcode, d, dir)).into()),
+        None => return Err(ErrorKind::NotSupported(format!(
+            "Bidirectional NVMe {} opcode 0x{:02x} is not supported by \
+             linux kernel", cmd_type, cmd.opcode)).into()),
+    }
+    if dir == NvmeDataDirection::None && (data_len != 0 || metadata_len != 0)
+    {
+        return Err(ErrorKind::InvalidArgument(format!(
+            "NVMe {} opcode 0x{:02x} transfers no data, but buffer \
+             provided", cmd_type, cmd.opcode)).into());
+    }
+    if dir != NvmeDataDirection::None && data_len == 0 {
+        return Err(ErrorKind::InvalidArgument(format!(
+            "NVMe {} opcode 0x{:02x} transfers data, but buffer is empty",
+            cmd_type, cmd.opcode)).into());
+    }
+    if data_len > u32::MAX as usize || metadata_len > u32::MAX as usize {
+        return Err(ErrorKind::InvalidArgument(format!(
+            "Buffer of NVMe {} opcode 0x{:02x} exceeds 4 GiB", cmd_type,
+            cmd.opcode)).into());
+    }
+    Ok(())
+}
+
+/// Transport issuing NVMe passthrough commands to kernel, implemented by
+/// `NvmeUringTransport` of the `io_uring` cargo feature.
+/// `NvmeController::admin_passthru()` and `NvmeNameSpace::io_passthru()`
+/// use ioctl on device node instead.
+pub trait NvmeCmdTransport: Send + Sync {
+    /// Issue admin command. Error is only returned when the command could
+    /// not be issued, NVMe status is returned in the completion.
+    fn admin_cmd(&self, cmd: &NvmeCmd, data: &mut [u8])
+        -> Result<NvmeCompletion>;
+
+    /// Issue I/O command. Error is only returned when the command could
+    /// not be issued, NVMe status is returned in the completion.
+    fn io_cmd(&self, cmd: &NvmeCmd, data: &mut [u8])
+        -> Result<NvmeCompletion>;
+}
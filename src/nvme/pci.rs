@@ -0,0 +1,157 @@
+/*
+ * Copyright (C) 2017 Red Hat, Inc.
+ * This library is free software; you can redistribute it and/or
+ * modify it under the terms of the GNU Lesser General Public
+ * License as published by the Free Software Foundation; either
+ * version 2.1 of the License, or (at your option) any later version.
+ *
+ * This library is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+ * Lesser General Public License for more details.
+ *
+ * You should have received a copy of the GNU Lesser General Public
+ * License along with this library; If not, see <http://www.gnu.org/licenses/>.
+ *
+ * Author: Gris Ge <fge@redhat.com>
+ */
+
+use std::fs::{canonicalize, read_link};
+use std::path::Path;
+
+use super::error::*;
+use super::root::SystemRoot;
+use super::utils::{dir_entry_names_get, sysfs_read_str};
+
+static SYSFS_PCI_SLOTS_FOLDER: &str = "bus/pci/slots";
+
+/// PCI function of a PCIe NVMe controller.
+#[derive(Debug, Clone)]
+pub struct NvmePciInfo {
+    bdf:                        String,
+    vendor_id:                  u16,
+    device_id:                  u16,
+    subsystem_vendor_id:        u16,
+    subsystem_device_id:        u16,
+    cur_link_speed:             String,
+    cur_link_width:             u32,
+    max_link_speed:             String,
+    max_link_width:             u32,
+    numa_node:                  i32,
+    iommu_group:                Option<u32>,
+    slot:                       Option<String>,
+}
+
+impl NvmePciInfo {
+    /// PCI address in `domain:bus:device.function` format, e.g.
+    /// `0000:01:00.0`.
+    pub fn bdf_get(&self) -> &str { &self.bdf }
+    pub fn vendor_id_get(&self) -> u16 { self.vendor_id }
+    pub fn device_id_get(&self) -> u16 { self.device_id }
+    pub fn subsystem_vendor_id_get(&self) -> u16 {
+        self.subsystem_vendor_id
+    }
+    pub fn subsystem_device_id_get(&self) -> u16 {
+        self.subsystem_device_id
+    }
+    /// Negotiated PCIe link speed, e.g. `8.0 GT/s PCIe`.
+    pub fn cur_link_speed_get(&self) -> &str { &self.cur_link_speed }
+    /// Negotiated PCIe link width, e.g. 4 for x4.
+    pub fn cur_link_width_get(&self) -> u32 { self.cur_link_width }
+    pub fn max_link_speed_get(&self) -> &str { &self.max_link_speed }
+    pub fn max_link_width_get(&self) -> u32 { self.max_link_width }
+    /// NUMA node of PCI function, -1 if not NUMA aware.
+    pub fn numa_node_get(&self) -> i32 { self.numa_node }
+    /// None if IOMMU is disabled.
+    pub fn iommu_group_get(&self) -> Option<u32> { self.iommu_group }
+    /// Physical slot name, None if platform does not provide slot
+    /// information.
+    pub fn slot_get(&self) -> Option<&str> {
+        self.slot.as_deref()
+    }
+
+    /// Whether the PCIe link is negotiated at lower speed or narrower
+    /// width than the device supports.
+    pub fn is_link_degraded(&self) -> bool {
+        self.cur_link_width < self.max_link_width ||
+            link_speed_gts(&self.cur_link_speed) <
+            link_speed_gts(&self.max_link_speed)
+    }
+
+    /* Resolve PCI function from `device` link of controller sysfs folder */
+    pub(crate) fn from_ctrl_sysfs(root: &SystemRoot, ctrl_sysfs_path: &str)
+        -> Result<NvmePciInfo> {
+        let pci_path = canonicalize(format!("{}/device", ctrl_sysfs_path))?;
+        let bdf = match pci_path.file_name().and_then(|n| n.to_str()) {
+            Some(n) => n.to_string(),
+            None => return Err(ErrorKind::LibBug(format!(
+                "Invalid PCI device path {}", pci_path.display())).into()),
+        };
+        let pci_path = pci_path.to_string_lossy().to_string();
+        let attr = |name: &str| {
+            sysfs_read_str(&format!("{}/{}", pci_path, name))
+        };
+        let hex_attr = |name: &str| -> Result<u16> {
+            let value = attr(name)?;
+            u16::from_str_radix(value.trim_start_matches("0x"), 16)
+                .map_err(|_| ErrorKind::CorruptedData(format!(
+                    "Invalid PCI {} '{}' of {}", name, value, bdf)).into())
+        };
+        /* Link attributes are not available on virtual functions of some
+         * kernel versions.
+         */
+        let width_attr = |name: &str| {
+            attr(name).ok().and_then(|w| w.parse::<u32>().ok()).unwrap_or(0)
+        };
+        let iommu_group = match read_link(format!("{}/iommu_group", pci_path)) {
+            Ok(p) => p.file_name().and_then(|n| n.to_str())
+                .and_then(|n| n.parse::<u32>().ok()),
+            Err(_) => None,
+        };
+        Ok(NvmePciInfo {
+            vendor_id:              hex_attr("vendor")?,
+            device_id:              hex_attr("device")?,
+            subsystem_vendor_id:    hex_attr("subsystem_vendor")?,
+            subsystem_device_id:    hex_attr("subsystem_device")?,
+            cur_link_speed:         attr("current_link_speed")
+                                        .unwrap_or_default(),
+            cur_link_width:         width_attr("current_link_width"),
+            max_link_speed:         attr("max_link_speed").unwrap_or_default(),
+            max_link_width:         width_attr("max_link_width"),
+            numa_node:              attr("numa_node").ok()
+                                        .and_then(|n| n.parse::<i32>().ok())
+                                        .unwrap_or(-1),
+            iommu_group,
+            slot:                   slot_name_get(root, &bdf),
+            bdf,
+        })
+    }
+}
+
+/* Physical slot address is the BDF without function number */
+fn slot_name_get(root: &SystemRoot, bdf: &str) -> Option<String> {
+    let slot_addr = match bdf.rfind('.') {
+        Some(i) => &bdf[..i],
+        None => return None,
+    };
+    let slots_path = root.sysfs_path(SYSFS_PCI_SLOTS_FOLDER);
+    if !Path::new(&slots_path).is_dir() {
+        return None;
+    }
+    for slot in dir_entry_names_get(&slots_path).unwrap_or_default() {
+        if let Ok(addr) = sysfs_read_str(
+            &format!("{}/{}/address", slots_path, slot)) {
+            if addr == slot_addr {
+                return Some(slot);
+            }
+        }
+    }
+    None
+}
+
+/* Link speed like `8.0 GT/s PCIe` in GT/s, 0 if unknown */
+fn link_speed_gts(speed: &str) -> f32 {
+    speed.split_whitespace().next()
+        .and_then(|s| s.parse::<f32>().ok())
+        .unwrap_or(0f32)
+}
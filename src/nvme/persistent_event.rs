@@ -0,0 +1,558 @@
+/*
+ * Copyright (C) 2017 Red Hat, Inc.
+ * This library is free software; you can redistribute it and/or
+ * modify it under the terms of the GNU Lesser General Public
+ * License as published by the Free Software Foundation; either
+ * version 2.1 of the License, or (at your option) any later version.
+ *
+ * This library is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+ * Lesser General Public License for more details.
+ *
+ * You should have received a copy of the GNU Lesser General Public
+ * License along with this library; If not, see <http://www.gnu.org/licenses/>.
+ *
+ * Author: Gris Ge <fge@redhat.com>
+ */
+
+use byteorder::{ByteOrder, LittleEndian};
+
+use super::error::*;
+use super::utils::ascii_padded_to_string;
+
+/* Action of Log Specific Parameter of Persistent Event log page */
+pub(crate) const NVME_PEL_ACTION_READ: u8 = 0x0;
+pub(crate) const NVME_PEL_ACTION_ESTABLISH: u8 = 0x1;
+pub(crate) const NVME_PEL_ACTION_RELEASE: u8 = 0x2;
+
+/* Log header, events follow it */
+pub(crate) const NVME_PEL_HEADER_LEN: usize = 512;
+
+/* Event Type(1), Event Type Revision(1), Event Header Length(1), the
+ * Event Header Length counts the bytes after these three.
+ */
+const NVME_PEL_EVENT_HEADER_FIXED_LEN: usize = 3;
+const NVME_PEL_EVENT_HEADER_MIN_LEN: usize = 24;
+
+const NVME_PEL_EVENT_SMART: u8 = 0x01;
+const NVME_PEL_EVENT_FW_COMMIT: u8 = 0x02;
+const NVME_PEL_EVENT_TIMESTAMP_CHANGE: u8 = 0x03;
+const NVME_PEL_EVENT_POWER_ON_RESET: u8 = 0x04;
+const NVME_PEL_EVENT_HW_ERROR: u8 = 0x05;
+const NVME_PEL_EVENT_CHANGE_NS: u8 = 0x06;
+const NVME_PEL_EVENT_FORMAT_START: u8 = 0x07;
+const NVME_PEL_EVENT_FORMAT_COMPLETION: u8 = 0x08;
+const NVME_PEL_EVENT_SANITIZE_START: u8 = 0x09;
+const NVME_PEL_EVENT_SANITIZE_COMPLETION: u8 = 0x0a;
+const NVME_PEL_EVENT_SET_FEATURE: u8 = 0x0b;
+const NVME_PEL_EVENT_THERMAL_EXCURSION: u8 = 0x0d;
+
+const NVME_PEL_SMART_LEN: usize = 512;
+const NVME_PEL_FW_REV_LEN: usize = 8;
+const NVME_PEL_RESET_INFO_LEN: usize = 36;
+const NVME_PEL_SET_FEATURE_NDW_MASK: u32 = 0x7;
+
+/// Persistent Event log page header.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NvmePersistentEventHeader {
+    /// Total Number of Events (TNEV).
+    pub tnev:                   u32,
+    /// Total Log Length (TLL) in bytes, including this header.
+    pub tll:                    u64,
+    pub log_revision:           u8,
+    /// Timestamp when this log page context was established, bits 47:0
+    /// are milliseconds since 1970-01-01 UTC.
+    pub timestamp:              u64,
+    pub power_on_hours:         u128,
+    pub power_cycle_count:      u64,
+    pub vid:                    u16,
+    pub ssvid:                  u16,
+    pub sn:                     String,
+    pub mn:                     String,
+    pub subnqn:                 String,
+    pub generation_number:      u16,
+    /// Reporting Context Information (RCI).
+    pub rci:                    u32,
+    supported_events:           [u8; 32],
+}
+
+impl NvmePersistentEventHeader {
+    /// Whether controller records events of `event_type`.
+    pub fn is_event_supported(&self, event_type: u8) -> bool {
+        self.supported_events[(event_type / 8) as usize] &
+            (1 << (event_type % 8)) != 0
+    }
+
+    pub(crate) fn parse(data: &[u8]) -> Result<NvmePersistentEventHeader> {
+        if data.len() < NVME_PEL_HEADER_LEN {
+            return Err(ErrorKind::CorruptedData(
+                "Persistent Event log page header is truncated".to_string())
+                .into());
+        }
+        let mut supported_events = [0u8; 32];
+        supported_events.copy_from_slice(&data[480..512]);
+        Ok(NvmePersistentEventHeader {
+            tnev:               LittleEndian::read_u32(&data[4..8]),
+            tll:                LittleEndian::read_u64(&data[8..16]),
+            log_revision:       data[16],
+            timestamp:          LittleEndian::read_u64(&data[20..28]),
+            power_on_hours:     LittleEndian::read_u128(&data[28..44]),
+            power_cycle_count:  LittleEndian::read_u64(&data[44..52]),
+            vid:                LittleEndian::read_u16(&data[52..54]),
+            ssvid:              LittleEndian::read_u16(&data[54..56]),
+            sn:                 ascii_padded_to_string(&data[56..76]),
+            mn:                 ascii_padded_to_string(&data[76..116]),
+            subnqn:             ascii_padded_to_string(&data[116..372]),
+            generation_number:  LittleEndian::read_u16(&data[372..374]),
+            rci:                LittleEndian::read_u32(&data[374..378]),
+            supported_events,
+        })
+    }
+}
+
+/// Power-on or Reset Information descriptor of a controller.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NvmePowerOnResetInfo {
+    pub cntlid:                 u16,
+    /// Firmware activation occurred.
+    pub fw_activated:           bool,
+    /// Operation in progress when power-on or reset happened.
+    pub op_in_progress:         u8,
+    pub ctrl_power_cycle:       u32,
+    pub power_on_ms:            u64,
+    pub ctrl_timestamp:         u64,
+}
+
+/// Event data of Persistent Event log page.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NvmePersistentEventData {
+    /// SMART / Health Information log page (02h) snapshot.
+    SmartSnapshot(Vec<u8>),
+    FwCommit {
+        old_fr:                 String,
+        new_fr:                 String,
+        /// Commit Action of Firmware Commit command.
+        action:                 u8,
+        slot:                   u8,
+        sct:                    u8,
+        sc:                     u8,
+        vendor_result:          u16,
+    },
+    TimestampChange {
+        previous:               u64,
+        ms_since_reset:         u64,
+    },
+    PowerOnReset {
+        fr:                     String,
+        infos:                  Vec<NvmePowerOnResetInfo>,
+    },
+    /// NVM Subsystem Hardware Error.
+    HwError {
+        code:                   u16,
+        info:                   Vec<u8>,
+    },
+    ChangeNs {
+        /// CDW10 of Namespace Management command.
+        cdw10:                  u32,
+        nsze:                   u64,
+        nscap:                  u64,
+        flbas:                  u8,
+        dps:                    u8,
+        nmic:                   u8,
+        anagrpid:               u32,
+        nvmsetid:               u16,
+        nsid:                   u32,
+    },
+    FormatStart {
+        nsid:                   u32,
+        /// Format NVM Attributes of Identify Controller.
+        fna:                    u8,
+        cdw10:                  u32,
+    },
+    FormatCompletion {
+        nsid:                   u32,
+        /// Smallest Format Progress Indicator.
+        smallest_fpi:           u8,
+        status:                 u8,
+        completion_info:        u16,
+        /// Status field of Format NVM command completion.
+        status_field:           u32,
+    },
+    SanitizeStart {
+        sanicap:                u32,
+        cdw10:                  u32,
+        cdw11:                  u32,
+    },
+    SanitizeCompletion {
+        /// Sanitize Progress (SPROG).
+        sprog:                  u16,
+        /// Sanitize Status (SSTAT).
+        sstat:                  u16,
+        completion_info:        u16,
+    },
+    SetFeature {
+        /// Recorded command dwords starting from CDW10.
+        cdws:                   Vec<u32>,
+        /// Data transferred by Set Features command, if recorded.
+        mem_buf:                Vec<u8>,
+    },
+    ThermalExcursion {
+        /// Temperature over threshold in Kelvin, zero when under
+        /// threshold.
+        over_temp:              u8,
+        /// Threshold in Kelvin.
+        threshold:              u8,
+    },
+    /// Vendor specific or not decoded event, holding raw event data.
+    Unknown(Vec<u8>),
+}
+
+/// Event of Persistent Event log page.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NvmePersistentEvent {
+    pub event_type:             u8,
+    pub revision:               u8,
+    pub cntlid:                 u16,
+    /// Event timestamp, bits 47:0 are milliseconds since 1970-01-01 UTC.
+    pub timestamp:              u64,
+    pub vendor_info:            Vec<u8>,
+    pub data:                   NvmePersistentEventData,
+}
+
+/// Persistent Event log page (0Dh).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NvmePersistentEventLog {
+    pub header:                 NvmePersistentEventHeader,
+    pub events:                 Vec<NvmePersistentEvent>,
+}
+
+impl NvmePersistentEventLog {
+    pub(crate) fn parse(data: &[u8]) -> Result<NvmePersistentEventLog> {
+        let header = NvmePersistentEventHeader::parse(data)?;
+        /* TNEV comes from controller, never reserve more events than the
+         * buffer could hold.
+         */
+        let mut events = Vec::with_capacity(
+            (header.tnev as usize).min((data.len() - NVME_PEL_HEADER_LEN) /
+                                       NVME_PEL_EVENT_HEADER_MIN_LEN));
+        let mut offset = NVME_PEL_HEADER_LEN;
+        for _ in 0..header.tnev {
+            if offset + NVME_PEL_EVENT_HEADER_MIN_LEN > data.len() {
+                return Err(ErrorKind::CorruptedData(format!(
+                    "Persistent event header at offset {} exceeds the log \
+                     page length", offset)).into());
+            }
+            let entry = &data[offset..];
+            let ehl = entry[2] as usize + NVME_PEL_EVENT_HEADER_FIXED_LEN;
+            let vsil = LittleEndian::read_u16(&entry[20..22]) as usize;
+            let el = LittleEndian::read_u16(&entry[22..24]) as usize;
+            if ehl < NVME_PEL_EVENT_HEADER_MIN_LEN || vsil > el ||
+               ehl + el > entry.len() {
+                return Err(ErrorKind::CorruptedData(format!(
+                    "Persistent event at offset {} has invalid length",
+                    offset)).into());
+            }
+            events.push(NvmePersistentEvent {
+                event_type:     entry[0],
+                revision:       entry[1],
+                cntlid:         LittleEndian::read_u16(&entry[4..6]),
+                timestamp:      LittleEndian::read_u64(&entry[6..14]),
+                vendor_info:    entry[ehl..ehl + vsil].to_vec(),
+                data:           NvmePersistentEventData::parse(
+                    entry[0], &entry[ehl + vsil..ehl + el]),
+            });
+            offset += ehl + el;
+        }
+        Ok(NvmePersistentEventLog {
+            header,
+            events,
+        })
+    }
+}
+
+impl NvmePersistentEventData {
+    /* Event data shorter than defined is treated as unknown event */
+    fn parse(event_type: u8, d: &[u8]) -> NvmePersistentEventData {
+        let min_len = match event_type {
+            NVME_PEL_EVENT_SMART => NVME_PEL_SMART_LEN,
+            NVME_PEL_EVENT_FW_COMMIT => 22,
+            NVME_PEL_EVENT_TIMESTAMP_CHANGE => 16,
+            NVME_PEL_EVENT_POWER_ON_RESET => NVME_PEL_FW_REV_LEN,
+            NVME_PEL_EVENT_HW_ERROR => 4,
+            NVME_PEL_EVENT_CHANGE_NS => 48,
+            NVME_PEL_EVENT_FORMAT_START => 12,
+            NVME_PEL_EVENT_FORMAT_COMPLETION => 12,
+            NVME_PEL_EVENT_SANITIZE_START => 12,
+            NVME_PEL_EVENT_SANITIZE_COMPLETION => 8,
+            NVME_PEL_EVENT_SET_FEATURE => 4,
+            NVME_PEL_EVENT_THERMAL_EXCURSION => 2,
+            _ => return NvmePersistentEventData::Unknown(d.to_vec()),
+        };
+        if d.len() < min_len {
+            return NvmePersistentEventData::Unknown(d.to_vec());
+        }
+        match event_type {
+            NVME_PEL_EVENT_SMART => NvmePersistentEventData::SmartSnapshot(
+                d[..NVME_PEL_SMART_LEN].to_vec()),
+            NVME_PEL_EVENT_FW_COMMIT => NvmePersistentEventData::FwCommit {
+                old_fr:         ascii_padded_to_string(&d[0..8]),
+                new_fr:         ascii_padded_to_string(&d[8..16]),
+                action:         d[16],
+                slot:           d[17],
+                sct:            d[18],
+                sc:             d[19],
+                vendor_result:  LittleEndian::read_u16(&d[20..22]),
+            },
+            NVME_PEL_EVENT_TIMESTAMP_CHANGE =>
+                NvmePersistentEventData::TimestampChange {
+                    previous:       LittleEndian::read_u64(&d[0..8]),
+                    ms_since_reset: LittleEndian::read_u64(&d[8..16]),
+                },
+            NVME_PEL_EVENT_POWER_ON_RESET =>
+                NvmePersistentEventData::PowerOnReset {
+                    fr:             ascii_padded_to_string(
+                        &d[..NVME_PEL_FW_REV_LEN]),
+                    infos:          d[NVME_PEL_FW_REV_LEN..]
+                        .chunks_exact(NVME_PEL_RESET_INFO_LEN)
+                        .map(|i| NvmePowerOnResetInfo {
+                            cntlid:     LittleEndian::read_u16(&i[0..2]),
+                            fw_activated: i[2] != 0,
+                            op_in_progress: i[3],
+                            ctrl_power_cycle:
+                                LittleEndian::read_u32(&i[16..20]),
+                            power_on_ms: LittleEndian::read_u64(&i[20..28]),
+                            ctrl_timestamp:
+                                LittleEndian::read_u64(&i[28..36]),
+                        })
+                        .collect(),
+                },
+            NVME_PEL_EVENT_HW_ERROR => NvmePersistentEventData::HwError {
+                code:           LittleEndian::read_u16(&d[0..2]),
+                info:           d[4..].to_vec(),
+            },
+            NVME_PEL_EVENT_CHANGE_NS => NvmePersistentEventData::ChangeNs {
+                cdw10:          LittleEndian::read_u32(&d[0..4]),
+                nsze:           LittleEndian::read_u64(&d[8..16]),
+                nscap:          LittleEndian::read_u64(&d[24..32]),
+                flbas:          d[32],
+                dps:            d[33],
+                nmic:           d[34],
+                anagrpid:       LittleEndian::read_u32(&d[36..40]),
+                nvmsetid:       LittleEndian::read_u16(&d[40..42]),
+                nsid:           LittleEndian::read_u32(&d[44..48]),
+            },
+            NVME_PEL_EVENT_FORMAT_START =>
+                NvmePersistentEventData::FormatStart {
+                    nsid:           LittleEndian::read_u32(&d[0..4]),
+                    fna:            d[4],
+                    cdw10:          LittleEndian::read_u32(&d[8..12]),
+                },
+            NVME_PEL_EVENT_FORMAT_COMPLETION =>
+                NvmePersistentEventData::FormatCompletion {
+                    nsid:           LittleEndian::read_u32(&d[0..4]),
+                    smallest_fpi:   d[4],
+                    status:         d[5],
+                    completion_info: LittleEndian::read_u16(&d[6..8]),
+                    status_field:   LittleEndian::read_u32(&d[8..12]),
+                },
+            NVME_PEL_EVENT_SANITIZE_START =>
+                NvmePersistentEventData::SanitizeStart {
+                    sanicap:        LittleEndian::read_u32(&d[0..4]),
+                    cdw10:          LittleEndian::read_u32(&d[4..8]),
+                    cdw11:          LittleEndian::read_u32(&d[8..12]),
+                },
+            NVME_PEL_EVENT_SANITIZE_COMPLETION =>
+                NvmePersistentEventData::SanitizeCompletion {
+                    sprog:          LittleEndian::read_u16(&d[0..2]),
+                    sstat:          LittleEndian::read_u16(&d[2..4]),
+                    completion_info: LittleEndian::read_u16(&d[4..6]),
+                },
+            NVME_PEL_EVENT_SET_FEATURE => {
+                /* Set Feature Event Layout: bits 2:0 are the number of
+                 * recorded command dwords.
+                 */
+                let layout = LittleEndian::read_u32(&d[0..4]);
+                let ndw = (layout & NVME_PEL_SET_FEATURE_NDW_MASK) as usize;
+                let cdws_end = (4 + ndw * 4).min(d.len());
+                NvmePersistentEventData::SetFeature {
+                    cdws:           d[4..cdws_end].chunks_exact(4)
+                        .map(LittleEndian::read_u32)
+                        .collect(),
+                    mem_buf:        d[cdws_end..].to_vec(),
+                }
+            },
+            NVME_PEL_EVENT_THERMAL_EXCURSION =>
+                NvmePersistentEventData::ThermalExcursion {
+                    over_temp:      d[0],
+                    threshold:      d[1],
+                },
+            _ => NvmePersistentEventData::Unknown(d.to_vec()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header_build(tnev: u32) -> Vec<u8> {
+        let mut data = vec![0u8; NVME_PEL_HEADER_LEN];
+        data[4..8].copy_from_slice(&tnev.to_le_bytes());
+        data[8..16].copy_from_slice(&4096u64.to_le_bytes());
+        data[16] = 1;
+        data[20..28].copy_from_slice(&0x1234_5678u64.to_le_bytes());
+        data[28..44].copy_from_slice(&(1u128 << 64 | 7).to_le_bytes());
+        data[44..52].copy_from_slice(&42u64.to_le_bytes());
+        data[52..54].copy_from_slice(&0x1b36u16.to_le_bytes());
+        data[54..56].copy_from_slice(&0x1af4u16.to_le_bytes());
+        data[56..76].copy_from_slice(b"SN0001              ");
+        data[76..80].copy_from_slice(b"QEMU");
+        data[116..130].copy_from_slice(b"nqn.2019-08.io");
+        data[372..374].copy_from_slice(&3u16.to_le_bytes());
+        data[374..378].copy_from_slice(&0x1_0001u32.to_le_bytes());
+        /* Firmware Commit (02h) and Thermal Excursion (0Dh) supported */
+        data[480] = 1 << NVME_PEL_EVENT_FW_COMMIT;
+        data[481] = 1 << (NVME_PEL_EVENT_THERMAL_EXCURSION - 8);
+        data
+    }
+
+    fn event_push(data: &mut Vec<u8>, event_type: u8, cntlid: u16,
+                  vendor_info: &[u8], event_data: &[u8]) {
+        let mut header = [0u8; NVME_PEL_EVENT_HEADER_MIN_LEN];
+        header[0] = event_type;
+        header[1] = 2;
+        header[2] = (NVME_PEL_EVENT_HEADER_MIN_LEN -
+                     NVME_PEL_EVENT_HEADER_FIXED_LEN) as u8;
+        header[4..6].copy_from_slice(&cntlid.to_le_bytes());
+        header[6..14].copy_from_slice(&0xabcdu64.to_le_bytes());
+        header[20..22].copy_from_slice(
+            &(vendor_info.len() as u16).to_le_bytes());
+        header[22..24].copy_from_slice(
+            &((vendor_info.len() + event_data.len()) as u16).to_le_bytes());
+        data.extend_from_slice(&header);
+        data.extend_from_slice(vendor_info);
+        data.extend_from_slice(event_data);
+    }
+
+    #[test]
+    fn test_header_parse() {
+        let header = NvmePersistentEventHeader::parse(&header_build(3))
+            .unwrap();
+        assert_eq!(header.tnev, 3);
+        assert_eq!(header.tll, 4096);
+        assert_eq!(header.log_revision, 1);
+        assert_eq!(header.timestamp, 0x1234_5678);
+        assert_eq!(header.power_on_hours, 1u128 << 64 | 7);
+        assert_eq!(header.power_cycle_count, 42);
+        assert_eq!(header.vid, 0x1b36);
+        assert_eq!(header.ssvid, 0x1af4);
+        assert_eq!(header.sn, "SN0001");
+        assert_eq!(header.mn, "QEMU");
+        assert_eq!(header.subnqn, "nqn.2019-08.io");
+        assert_eq!(header.generation_number, 3);
+        assert_eq!(header.rci, 0x1_0001);
+        assert!(header.is_event_supported(NVME_PEL_EVENT_FW_COMMIT));
+        assert!(header.is_event_supported(NVME_PEL_EVENT_THERMAL_EXCURSION));
+        assert!(!header.is_event_supported(NVME_PEL_EVENT_SMART));
+        assert!(!header.is_event_supported(0xff));
+    }
+
+    #[test]
+    fn test_parse_events() {
+        let mut data = header_build(4);
+        let mut fw_commit = Vec::new();
+        fw_commit.extend_from_slice(b"1.0     2.0     ");
+        fw_commit.extend_from_slice(&[3, 2, 1, 0x0b, 0x55, 0x00]);
+        event_push(&mut data, NVME_PEL_EVENT_FW_COMMIT, 1, &[], &fw_commit);
+        event_push(&mut data, NVME_PEL_EVENT_THERMAL_EXCURSION, 2,
+                   &[0xaa, 0xbb], &[0x5a, 0x50]);
+        let mut set_feature = Vec::new();
+        set_feature.extend_from_slice(&2u32.to_le_bytes());
+        set_feature.extend_from_slice(&0x7u32.to_le_bytes());
+        set_feature.extend_from_slice(&0x1u32.to_le_bytes());
+        set_feature.extend_from_slice(&[9, 9]);
+        event_push(&mut data, NVME_PEL_EVENT_SET_FEATURE, 1, &[],
+                   &set_feature);
+        event_push(&mut data, 0xde, 1, &[], &[1, 2, 3]);
+        let log = NvmePersistentEventLog::parse(&data).unwrap();
+
+        assert_eq!(log.events.len(), 4);
+        let event = &log.events[0];
+        assert_eq!(event.event_type, NVME_PEL_EVENT_FW_COMMIT);
+        assert_eq!(event.revision, 2);
+        assert_eq!(event.cntlid, 1);
+        assert_eq!(event.timestamp, 0xabcd);
+        assert!(event.vendor_info.is_empty());
+        assert_eq!(event.data, NvmePersistentEventData::FwCommit {
+            old_fr:             "1.0".to_string(),
+            new_fr:             "2.0".to_string(),
+            action:             3,
+            slot:               2,
+            sct:                1,
+            sc:                 0x0b,
+            vendor_result:      0x55,
+        });
+
+        let event = &log.events[1];
+        assert_eq!(event.cntlid, 2);
+        assert_eq!(event.vendor_info, vec![0xaa, 0xbb]);
+        assert_eq!(event.data, NvmePersistentEventData::ThermalExcursion {
+            over_temp:          0x5a,
+            threshold:          0x50,
+        });
+
+        assert_eq!(log.events[2].data, NvmePersistentEventData::SetFeature {
+            cdws:               vec![0x7, 0x1],
+            mem_buf:            vec![9, 9],
+        });
+        assert_eq!(log.events[3].data,
+                   NvmePersistentEventData::Unknown(vec![1, 2, 3]));
+    }
+
+    #[test]
+    fn test_parse_short_event_data() {
+        let mut data = header_build(1);
+        event_push(&mut data, NVME_PEL_EVENT_FW_COMMIT, 1, &[], &[0u8; 10]);
+        let log = NvmePersistentEventLog::parse(&data).unwrap();
+        assert_eq!(log.events[0].data,
+                   NvmePersistentEventData::Unknown(vec![0u8; 10]));
+    }
+
+    #[test]
+    fn test_parse_empty() {
+        let log = NvmePersistentEventLog::parse(&header_build(0)).unwrap();
+        assert!(log.events.is_empty());
+    }
+
+    #[test]
+    fn test_parse_truncated() {
+        assert!(NvmePersistentEventLog::parse(
+            &header_build(0)[..NVME_PEL_HEADER_LEN - 1]).is_err());
+        /* More events claimed than present */
+        assert!(NvmePersistentEventLog::parse(&header_build(u32::MAX))
+                .is_err());
+        let mut data = header_build(2);
+        event_push(&mut data, NVME_PEL_EVENT_THERMAL_EXCURSION, 1, &[],
+                   &[1, 2]);
+        assert!(NvmePersistentEventLog::parse(&data).is_err());
+        /* Event length exceeds the buffer */
+        data.truncate(data.len() - 1);
+        data[4] = 1;
+        assert!(NvmePersistentEventLog::parse(&data).is_err());
+    }
+
+    #[test]
+    fn test_parse_invalid_event_len() {
+        let mut data = header_build(1);
+        event_push(&mut data, NVME_PEL_EVENT_THERMAL_EXCURSION, 1, &[],
+                   &[1, 2]);
+        /* Vendor specific information longer than event */
+        data[NVME_PEL_HEADER_LEN + 20] = 3;
+        assert!(NvmePersistentEventLog::parse(&data).is_err());
+        /* Event header shorter than defined */
+        data[NVME_PEL_HEADER_LEN + 20] = 0;
+        data[NVME_PEL_HEADER_LEN + 2] = 1;
+        assert!(NvmePersistentEventLog::parse(&data).is_err());
+    }
+}
@@ -0,0 +1,67 @@
+/*
+ * Copyright (C) 2017 Red Hat, Inc.
+ * This library is free software; you can redistribute it and/or
+ * modify it under the terms of the GNU Lesser General Public
+ * License as published by the Free Software Foundation; either
+ * version 2.1 of the License, or (at your option) any later version.
+ *
+ * This library is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+ * Lesser General Public License for more details.
+ *
+ * You should have received a copy of the GNU Lesser General Public
+ * License along with this library; If not, see <http://www.gnu.org/licenses/>.
+ *
+ * Author: Gris Ge <fge@redhat.com>
+ */
+
+static DEFAULT_SYSFS_ROOT: &str = "/sys";
+static DEFAULT_DEV_ROOT: &str = "/dev";
+
+/// Root folders of sysfs and device nodes used for discovery. Default is
+/// `/sys` and `/dev`. Could be pointed to a fake directory tree for
+/// testing or to remapped mounts inside containers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SystemRoot {
+    sysfs_root:                 String,
+    dev_root:                   String,
+}
+
+impl Default for SystemRoot {
+    fn default() -> SystemRoot {
+        SystemRoot::new(DEFAULT_SYSFS_ROOT, DEFAULT_DEV_ROOT)
+    }
+}
+
+impl SystemRoot {
+    pub fn new(sysfs_root: &str, dev_root: &str) -> SystemRoot {
+        SystemRoot {
+            sysfs_root:         sysfs_root.trim_end_matches('/').to_string(),
+            dev_root:           dev_root.trim_end_matches('/').to_string(),
+        }
+    }
+
+    pub fn sysfs_root_get(&self) -> &str {
+        &self.sysfs_root
+    }
+
+    pub fn dev_root_get(&self) -> &str {
+        &self.dev_root
+    }
+
+    /* Path of sysfs file or folder, `sub_path` should not start with '/' */
+    pub(crate) fn sysfs_path(&self, sub_path: &str) -> String {
+        format!("{}/{}", self.sysfs_root, sub_path)
+    }
+
+    /* Path of device node, e.g. `dev_path("nvme0")` */
+    pub(crate) fn dev_path(&self, dev_name: &str) -> String {
+        format!("{}/{}", self.dev_root, dev_name)
+    }
+
+    /* Path of /sys/class/nvme/<ctrl_name> */
+    pub(crate) fn ctrl_sysfs_path(&self, ctrl_name: &str) -> String {
+        self.sysfs_path(&format!("class/nvme/{}", ctrl_name))
+    }
+}
@@ -20,7 +20,7 @@ pub(crate) const MAX_LBA_FORMAT_COUNT: usize = 16;
 /* Maximum 16 LBA format is allowed */
 
 #[repr(C, packed)]
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Default)]
 pub(crate) struct NvmeSpecPsd {
     mp:                             [u8; 2],
     reserved_0:                     u8,
@@ -39,28 +39,6 @@ pub(crate) struct NvmeSpecPsd {
     reserved_9:                     [u8; 9],
 }
 
-impl Default for NvmeSpecPsd {
-    fn default() -> NvmeSpecPsd {
-        NvmeSpecPsd {
-            mp:                     [0; 2],
-            reserved_0:             0,
-            _bit_field_0:           0,
-            enlat:                  [0; 4],
-            exlat:                  [0; 4],
-            _bit_field_1:           0,
-            _bit_field_2:           0,
-            _bit_field_3:           0,
-            _bit_field_4:           0,
-            idlp:                   [0; 2],
-            _bit_field_5:           0,
-            reserved_7:             0,
-            actp:                   [0; 2],
-            _bit_field_6:           0,
-            reserved_9:             [0; 9],
-        }
-    }
-}
-
 #[repr(C, packed)]
 pub(crate) struct NvmeSpecIdCtrlData {
     pub(crate) vid:                 [u8; 2],
@@ -80,7 +58,11 @@ pub(crate) struct NvmeSpecIdCtrlData {
     pub(crate) ctratt:              [u8; 4],
     pub(crate) reserved_0:          [u8; 12],
     pub(crate) fguid:               [u8; 16],
-    pub(crate) reserved_1:          [u8; 112],
+    pub(crate) reserved_1:          [u8; 6],
+    pub(crate) crdt1:               [u8; 2],
+    pub(crate) crdt2:               [u8; 2],
+    pub(crate) crdt3:               [u8; 2],
+    pub(crate) reserved_1b:         [u8; 100],
     pub(crate) ressered_mi:         [u8; 16],
     pub(crate) oacs:                [u8; 2],
     pub(crate) acl:                 u8,
@@ -107,7 +89,16 @@ pub(crate) struct NvmeSpecIdCtrlData {
     pub(crate) mntmt:               [u8; 2],
     pub(crate) mxtmt:               [u8; 2],
     pub(crate) sanicap:             [u8; 4],
-    pub(crate) reserved_2:          [u8; 180],
+    pub(crate) hmminds:             [u8; 4],
+    pub(crate) hmmaxd:              [u8; 2],
+    pub(crate) nsetidmax:           [u8; 2],
+    pub(crate) endgidmax:           [u8; 2],
+    pub(crate) anatt:               u8,
+    pub(crate) anacap:              u8,
+    pub(crate) anagrpmax:           [u8; 4],
+    pub(crate) nanagrpid:           [u8; 4],
+    pub(crate) pels:                [u8; 4],
+    pub(crate) reserved_2:          [u8; 156],
     pub(crate) sqes:                u8,
     pub(crate) cqes:                u8,
     pub(crate) maxcmd:              [u8; 2],
@@ -123,7 +114,8 @@ pub(crate) struct NvmeSpecIdCtrlData {
     pub(crate) acwu:                [u8; 2],
     pub(crate) reserved_4:          [u8; 2],
     pub(crate) sgls:                [u8; 4],
-    pub(crate) reserved_5:          [u8; 228],
+    pub(crate) mnan:                [u8; 4],
+    pub(crate) reserved_5:          [u8; 224],
     pub(crate) subnqn:              [u8; 256],
     pub(crate) reserved_6:          [u8; 768],
     /* Below are for NVMe Fabric */
@@ -158,7 +150,11 @@ impl Default for NvmeSpecIdCtrlData {
             ctratt:                 [0; 4],
             reserved_0:             [0; 12],
             fguid:                  [0; 16],
-            reserved_1:             [0; 112],
+            reserved_1:             [0; 6],
+            crdt1:                  [0; 2],
+            crdt2:                  [0; 2],
+            crdt3:                  [0; 2],
+            reserved_1b:            [0; 100],
             ressered_mi:            [0; 16],
             oacs:                   [0; 2],
             acl:                    0,
@@ -185,7 +181,16 @@ impl Default for NvmeSpecIdCtrlData {
             mntmt:                  [0; 2],
             mxtmt:                  [0; 2],
             sanicap:                [0; 4],
-            reserved_2:             [0; 180],
+            hmminds:                [0; 4],
+            hmmaxd:                 [0; 2],
+            nsetidmax:              [0; 2],
+            endgidmax:              [0; 2],
+            anatt:                  0,
+            anacap:                 0,
+            anagrpmax:              [0; 4],
+            nanagrpid:              [0; 4],
+            pels:                   [0; 4],
+            reserved_2:             [0; 156],
             sqes:                   0,
             cqes:                   0,
             maxcmd:                 [0; 2],
@@ -201,7 +206,8 @@ impl Default for NvmeSpecIdCtrlData {
             acwu:                   [0; 2],
             reserved_4:             [0; 2],
             sgls:                   [0; 4],
-            reserved_5:             [0; 228],
+            mnan:                   [0; 4],
+            reserved_5:             [0; 224],
             subnqn:                 [0; 256],
             reserved_6:             [0; 768],
             ioccsz:                 [0; 4],
@@ -0,0 +1,183 @@
+/*
+ * Copyright (C) 2017 Red Hat, Inc.
+ * This library is free software; you can redistribute it and/or
+ * modify it under the terms of the GNU Lesser General Public
+ * License as published by the Free Software Foundation; either
+ * version 2.1 of the License, or (at your option) any later version.
+ *
+ * This library is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+ * Lesser General Public License for more details.
+ *
+ * You should have received a copy of the GNU Lesser General Public
+ * License along with this library; If not, see <http://www.gnu.org/licenses/>.
+ *
+ * Author: Gris Ge <fge@redhat.com>
+ */
+
+use std::path::Path;
+
+use super::controller::{NvmeScanFailure, NvmeScanResult};
+use super::discovery::{is_ctrl_name, is_ns_name};
+use super::error::*;
+use super::root::SystemRoot;
+use super::utils::{dir_entry_names_get, sysfs_read_str};
+use super::NvmeController;
+
+static SYSFS_NVME_SUBSYS_FOLDER: &str = "class/nvme-subsystem";
+static SYSFS_NVME_MULTIPATH_PARAM: &str =
+    "module/nvme_core/parameters/multipath";
+
+/// NVM subsystem as linux kernel shows in
+/// `/sys/class/nvme-subsystem/nvme-subsysN`. A subsystem could have
+/// multiple controllers (e.g. dual-ported drive) sharing namespaces, with
+/// native NVMe multipath the shared namespace is exposed as a single
+/// block device `nvmeXnY` (multipath head).
+pub struct NvmeSubsystem {
+    name:                       String,
+    nqn:                        String,
+    model:                      String,
+    serial:                     String,
+    firmware:                   String,
+    iopolicy:                   String,
+    subsystype:                 String,
+    multipath:                  bool,
+    ctrl_names:                 Vec<String>,
+    ns_names:                   Vec<String>,
+    root:                       SystemRoot,
+}
+
+impl NvmeSubsystem {
+    /// Kernel name of subsystem, e.g. `nvme-subsys0`.
+    pub fn name_get(&self) -> &str { &self.name }
+    /// NVM Subsystem NVMe Qualified Name.
+    pub fn nqn_get(&self) -> &str { &self.nqn }
+    pub fn model_get(&self) -> &str { &self.model }
+    pub fn serial_get(&self) -> &str { &self.serial }
+    pub fn firmware_get(&self) -> &str { &self.firmware }
+    /// Multipath I/O policy, e.g. `numa`, `round-robin` or `queue-depth`.
+    /// Empty if kernel is built without native NVMe multipath support,
+    /// which could still be disabled, see `is_multipath()`.
+    pub fn iopolicy_get(&self) -> &str { &self.iopolicy }
+    /// Subsystem type, e.g. `nvm` or `discovery`. Empty on old kernels.
+    pub fn subsystype_get(&self) -> &str { &self.subsystype }
+    /// Kernel names of controllers in this subsystem, e.g. `nvme0`.
+    pub fn ctrl_names_get(&self) -> &[String] { &self.ctrl_names }
+
+    /// Device paths of namespace block devices of this subsystem, e.g.
+    /// `/dev/nvme0n1`. With native NVMe multipath, they are multipath heads
+    /// shared by all controllers.
+    pub fn ns_blk_paths_get(&self) -> Vec<String> {
+        self.ns_names.iter().map(|n| self.root.dev_path(n)).collect()
+    }
+
+    /// Whether the shared namespaces are handled by kernel native NVMe
+    /// multipath, i.e. `multipath` parameter of `nvme_core` module is
+    /// enabled.
+    pub fn is_multipath(&self) -> bool {
+        self.multipath
+    }
+
+    /// Open all controllers of this subsystem. A failure on one controller
+    /// is reported in `NvmeScanResult.failures`.
+    pub fn controllers_get(&self) -> NvmeScanResult {
+        let mut ret = NvmeScanResult::default();
+        for ctrl_name in &self.ctrl_names {
+            let blk_path = self.root.dev_path(ctrl_name);
+            match NvmeController::from_path_with_root(&blk_path, &self.root) {
+                Ok(ctrl) => ret.controllers.push(ctrl),
+                Err(error) =>
+                    ret.failures.push(NvmeScanFailure{blk_path, error}),
+            }
+        }
+        ret
+    }
+
+    pub fn from_name(name: &str) -> Result<NvmeSubsystem> {
+        NvmeSubsystem::from_name_with_root(name, &SystemRoot::default())
+    }
+
+    /// Load subsystem `name`, e.g. `nvme-subsys0`, from specified sysfs
+    /// and device root folders.
+    pub fn from_name_with_root(name: &str, root: &SystemRoot)
+        -> Result<NvmeSubsystem> {
+        let sysfs_path = root.sysfs_path(
+            &format!("{}/{}", SYSFS_NVME_SUBSYS_FOLDER, name));
+        if !Path::new(&sysfs_path).is_dir() {
+            return Err(ErrorKind::InvalidArgument(format!(
+                "NVMe subsystem {} not found", name)).into());
+        }
+        let attr = |attr_name: &str| {
+            sysfs_read_str(&format!("{}/{}", sysfs_path, attr_name))
+        };
+        let mut ctrl_names = Vec::new();
+        let mut ns_names = Vec::new();
+        for entry_name in dir_entry_names_get(&sysfs_path)? {
+            if is_ctrl_name(&entry_name) {
+                ctrl_names.push(entry_name);
+            } else if is_ns_name(&entry_name) {
+                ns_names.push(entry_name);
+            }
+        }
+        /* Without the module parameter, e.g. kernel built without native
+         * multipath, namespace heads only exist when multipath is used.
+         */
+        let multipath = match sysfs_read_str(
+            &root.sysfs_path(SYSFS_NVME_MULTIPATH_PARAM)) {
+            Ok(v) => v == "Y",
+            Err(_) => !ns_names.is_empty(),
+        };
+        Ok(NvmeSubsystem {
+            name:               name.to_string(),
+            nqn:                attr("subsysnqn")?,
+            model:              attr("model").unwrap_or_default(),
+            serial:             attr("serial").unwrap_or_default(),
+            firmware:           attr("firmware_rev").unwrap_or_default(),
+            iopolicy:           attr("iopolicy").unwrap_or_default(),
+            subsystype:         attr("subsystype").unwrap_or_default(),
+            multipath,
+            ctrl_names,
+            ns_names,
+            root:               root.clone(),
+        })
+    }
+
+    pub fn get_all() -> Result<Vec<NvmeSubsystem>> {
+        NvmeSubsystem::get_all_with_root(&SystemRoot::default())
+    }
+
+    /// Enumerate all NVM subsystems in specified sysfs and device root
+    /// folders. Empty list is returned on kernel without subsystem support.
+    pub fn get_all_with_root(root: &SystemRoot)
+        -> Result<Vec<NvmeSubsystem>> {
+        let mut ret = Vec::new();
+        let names = match dir_entry_names_get(
+            &root.sysfs_path(SYSFS_NVME_SUBSYS_FOLDER)) {
+            Ok(n) => n,
+            Err(_) => return Ok(ret),
+        };
+        for name in names {
+            /* Subsystem might be removed after we read the folder */
+            match NvmeSubsystem::from_name_with_root(&name, root) {
+                Ok(subsys) => ret.push(subsys),
+                Err(NvmeError{kind: ErrorKind::InvalidArgument(_)}) =>
+                    continue,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(ret)
+    }
+
+    /// Find the subsystem containing controller of specified kernel name.
+    pub(crate) fn from_ctrl_name(ctrl_name: &str, root: &SystemRoot)
+        -> Result<NvmeSubsystem> {
+        for subsys in NvmeSubsystem::get_all_with_root(root)? {
+            if subsys.ctrl_names.iter().any(|n| n == ctrl_name) {
+                return Ok(subsys);
+            }
+        }
+        Err(ErrorKind::InvalidArgument(format!(
+            "No NVMe subsystem found for controller {}", ctrl_name)).into())
+    }
+}
@@ -0,0 +1,227 @@
+/*
+ * Copyright (C) 2017 Red Hat, Inc.
+ * This library is free software; you can redistribute it and/or
+ * modify it under the terms of the GNU Lesser General Public
+ * License as published by the Free Software Foundation; either
+ * version 2.1 of the License, or (at your option) any later version.
+ *
+ * This library is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+ * Lesser General Public License for more details.
+ *
+ * You should have received a copy of the GNU Lesser General Public
+ * License along with this library; If not, see <http://www.gnu.org/licenses/>.
+ *
+ * Author: Gris Ge <fge@redhat.com>
+ */
+
+use super::error::*;
+use super::utils::to_u32;
+
+pub(crate) const NVME_LOG_SUPPORTED: u8 = 0x00;
+pub(crate) const NVME_LOG_ERROR: u8 = 0x01;
+pub(crate) const NVME_LOG_SMART: u8 = 0x02;
+pub(crate) const NVME_LOG_FW_SLOT: u8 = 0x03;
+pub(crate) const NVME_LOG_CMD_EFFECTS: u8 = 0x05;
+pub(crate) const NVME_LOG_TELEMETRY_HOST: u8 = 0x07;
+pub(crate) const NVME_LOG_TELEMETRY_CTRL: u8 = 0x08;
+pub(crate) const NVME_LOG_ANA: u8 = 0x0c;
+pub(crate) const NVME_LOG_PERSISTENT_EVENT: u8 = 0x0d;
+pub(crate) const NVME_LOG_FID_EFFECTS: u8 = 0x12;
+
+/* 256 dwords of Log Page Identifier Supported data structure */
+pub(crate) const NVME_SUPPORTED_LOG_LEN: usize = 1024;
+
+/* Log Page Attributes of Identify Controller data structure, bits 0, 2
+ * and 6 are attributes of other log pages rather than log page support.
+ */
+const NVME_LPA_CMD_EFFECTS: u8 = 1 << 1;
+const NVME_LPA_TELEMETRY: u8 = 1 << 3;
+const NVME_LPA_PERSISTENT_EVENT: u8 = 1 << 4;
+pub(crate) const NVME_LPA_SUPPORTED_LOG: u8 = 1 << 5;
+
+const NVME_LID_SUPPORTED_LSUPP: u32 = 1 << 0;
+const NVME_LID_SUPPORTED_IOS: u32 = 1 << 1;
+const NVME_LID_SUPPORTED_LIDSP_SHIFT: u32 = 16;
+
+/// Log Page Identifier Supported data structure of a single log page.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct NvmeLogPageSupport {
+    raw:                        u32,
+}
+
+impl NvmeLogPageSupport {
+    pub fn raw_get(&self) -> u32 { self.raw }
+
+    /// LID Supported (LSUPP).
+    pub fn is_supported(&self) -> bool {
+        self.raw & NVME_LID_SUPPORTED_LSUPP != 0
+    }
+
+    /// Index Offset Supported (IOS), the log page could be read by index
+    /// offset instead of byte offset.
+    pub fn ios(&self) -> bool { self.raw & NVME_LID_SUPPORTED_IOS != 0 }
+
+    /// LID Specific Parameter (LIDSP), meaning depends on log page.
+    pub fn lidsp_get(&self) -> u16 {
+        (self.raw >> NVME_LID_SUPPORTED_LIDSP_SHIFT) as u16
+    }
+}
+
+/// Supported Log Pages log page (00h).
+#[derive(Debug, Clone)]
+pub struct NvmeSupportedLogPages {
+    entries:                    Vec<NvmeLogPageSupport>,
+    // False when built from controller attributes, unlisted log pages are
+    // unknown instead of unsupported.
+    complete:                   bool,
+}
+
+impl NvmeSupportedLogPages {
+    pub fn lid_get(&self, lid: u8) -> NvmeLogPageSupport {
+        self.entries[lid as usize]
+    }
+
+    pub fn is_supported(&self, lid: u8) -> bool {
+        self.lid_get(lid).is_supported()
+    }
+
+    /// Identifiers of all supported log pages.
+    pub fn lids_get(&self) -> Vec<u8> {
+        self.entries.iter().enumerate()
+            .filter(|(_, e)| e.is_supported())
+            .map(|(lid, _)| lid as u8)
+            .collect()
+    }
+
+    /* Some(false) only when log page 00h says so, None when controller
+     * attributes do not tell.
+     */
+    pub(crate) fn support_get(&self, lid: u8) -> Option<bool> {
+        match (self.is_supported(lid), self.complete) {
+            (true, _) => Some(true),
+            (false, true) => Some(false),
+            (false, false) => None,
+        }
+    }
+
+    pub(crate) fn parse(data: &[u8]) -> Result<NvmeSupportedLogPages> {
+        if data.len() < NVME_SUPPORTED_LOG_LEN {
+            return Err(ErrorKind::CorruptedData(
+                "Supported Log Pages log page is truncated".to_string())
+                .into());
+        }
+        Ok(NvmeSupportedLogPages {
+            entries:            data[..NVME_SUPPORTED_LOG_LEN].chunks(4)
+                .map(|c| NvmeLogPageSupport {
+                    raw:        to_u32([c[0], c[1], c[2], c[3]]),
+                })
+                .collect(),
+            complete:           true,
+        })
+    }
+
+    /* For controller without Supported Log Pages log page: mandatory log
+     * pages and the ones indicated by Log Page Attributes, CMIC for ANA.
+     * Other log pages are unknown.
+     */
+    pub(crate) fn from_ctrl_attrs(lpa: u8, ana_supported: bool)
+        -> NvmeSupportedLogPages {
+        let mut lids = vec![NVME_LOG_ERROR, NVME_LOG_SMART, NVME_LOG_FW_SLOT];
+        if lpa & NVME_LPA_SUPPORTED_LOG != 0 {
+            lids.push(NVME_LOG_SUPPORTED);
+        }
+        if lpa & NVME_LPA_CMD_EFFECTS != 0 {
+            lids.push(NVME_LOG_CMD_EFFECTS);
+        }
+        if lpa & NVME_LPA_TELEMETRY != 0 {
+            lids.push(NVME_LOG_TELEMETRY_HOST);
+            lids.push(NVME_LOG_TELEMETRY_CTRL);
+        }
+        if ana_supported {
+            lids.push(NVME_LOG_ANA);
+        }
+        if lpa & NVME_LPA_PERSISTENT_EVENT != 0 {
+            lids.push(NVME_LOG_PERSISTENT_EVENT);
+        }
+        let mut entries = vec![NvmeLogPageSupport::default(); 256];
+        for lid in lids {
+            entries[lid as usize].raw = NVME_LID_SUPPORTED_LSUPP;
+        }
+        NvmeSupportedLogPages {
+            entries,
+            complete:           false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lid_set(data: &mut [u8], lid: u8, raw: u32) {
+        let off = lid as usize * 4;
+        data[off..off + 4].copy_from_slice(&raw.to_le_bytes());
+    }
+
+    #[test]
+    fn test_parse() {
+        let mut data = vec![0u8; NVME_SUPPORTED_LOG_LEN];
+        lid_set(&mut data, NVME_LOG_SMART, NVME_LID_SUPPORTED_LSUPP);
+        lid_set(&mut data, NVME_LOG_PERSISTENT_EVENT,
+                NVME_LID_SUPPORTED_LSUPP | NVME_LID_SUPPORTED_IOS |
+                0xabcd << NVME_LID_SUPPORTED_LIDSP_SHIFT);
+        lid_set(&mut data, 0xff, NVME_LID_SUPPORTED_LSUPP);
+        let logs = NvmeSupportedLogPages::parse(&data).unwrap();
+
+        assert_eq!(logs.lids_get(),
+                   vec![NVME_LOG_SMART, NVME_LOG_PERSISTENT_EVENT, 0xff]);
+        let pel = logs.lid_get(NVME_LOG_PERSISTENT_EVENT);
+        assert!(pel.is_supported());
+        assert!(pel.ios());
+        assert_eq!(pel.lidsp_get(), 0xabcd);
+        assert!(!logs.lid_get(NVME_LOG_SMART).ios());
+        assert_eq!(logs.support_get(NVME_LOG_SMART), Some(true));
+        assert_eq!(logs.support_get(NVME_LOG_ERROR), Some(false));
+    }
+
+    #[test]
+    fn test_parse_empty() {
+        let logs = NvmeSupportedLogPages::parse(
+            &vec![0u8; NVME_SUPPORTED_LOG_LEN]).unwrap();
+        assert!(logs.lids_get().is_empty());
+        assert_eq!(logs.support_get(NVME_LOG_SUPPORTED), Some(false));
+    }
+
+    #[test]
+    fn test_parse_truncated() {
+        for len in &[0, 4, NVME_SUPPORTED_LOG_LEN - 1] {
+            assert!(NvmeSupportedLogPages::parse(&vec![0xffu8; *len])
+                    .is_err());
+        }
+    }
+
+    #[test]
+    fn test_from_ctrl_attrs() {
+        let logs = NvmeSupportedLogPages::from_ctrl_attrs(
+            NVME_LPA_CMD_EFFECTS | NVME_LPA_SUPPORTED_LOG, true);
+        for lid in &[NVME_LOG_SUPPORTED, NVME_LOG_ERROR, NVME_LOG_SMART,
+                     NVME_LOG_FW_SLOT, NVME_LOG_CMD_EFFECTS, NVME_LOG_ANA] {
+            assert_eq!(logs.support_get(*lid), Some(true));
+        }
+        /* Not indicated by controller attributes is unknown */
+        for lid in &[NVME_LOG_TELEMETRY_HOST, NVME_LOG_TELEMETRY_CTRL,
+                     NVME_LOG_PERSISTENT_EVENT, NVME_LOG_FID_EFFECTS] {
+            assert_eq!(logs.support_get(*lid), None);
+        }
+
+        let logs = NvmeSupportedLogPages::from_ctrl_attrs(
+            NVME_LPA_TELEMETRY | NVME_LPA_PERSISTENT_EVENT, false);
+        assert_eq!(logs.support_get(NVME_LOG_TELEMETRY_HOST), Some(true));
+        assert_eq!(logs.support_get(NVME_LOG_TELEMETRY_CTRL), Some(true));
+        assert_eq!(logs.support_get(NVME_LOG_PERSISTENT_EVENT), Some(true));
+        assert_eq!(logs.support_get(NVME_LOG_SUPPORTED), None);
+        assert_eq!(logs.support_get(NVME_LOG_ANA), None);
+    }
+}
@@ -0,0 +1,256 @@
+/*
+ * Copyright (C) 2017 Red Hat, Inc.
+ * This library is free software; you can redistribute it and/or
+ * modify it under the terms of the GNU Lesser General Public
+ * License as published by the Free Software Foundation; either
+ * version 2.1 of the License, or (at your option) any later version.
+ *
+ * This library is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+ * Lesser General Public License for more details.
+ *
+ * You should have received a copy of the GNU Lesser General Public
+ * License along with this library; If not, see <http://www.gnu.org/licenses/>.
+ *
+ * Author: Gris Ge <fge@redhat.com>
+ */
+
+use std::fmt;
+
+use super::error::*;
+use super::supported_log::{NVME_LOG_TELEMETRY_CTRL, NVME_LOG_TELEMETRY_HOST};
+use super::utils::{ascii_padded_to_string, to_u16, to_u32};
+
+/* Telemetry log page is made of 512 bytes blocks, block 0 is the header */
+pub(crate) const NVME_TELEMETRY_BLOCK_LEN: usize = 512;
+pub(crate) const NVME_TELEMETRY_DATA_AREA_MAX: u8 = 4;
+/* Log Specific Parameter of host-initiated telemetry log page */
+pub(crate) const NVME_TELEMETRY_LSP_CREATE: u8 = 1;
+
+const NVME_TELEMETRY_REASON_OFFSET: usize = 384;
+const NVME_TELEMETRY_REASON_LEN: usize = 128;
+
+/* Valid Flags of Reason Identifier */
+const NVME_TELEMETRY_REASON_VLN: u8 = 1 << 0;
+const NVME_TELEMETRY_REASON_VFID: u8 = 1 << 1;
+const NVME_TELEMETRY_REASON_VEI: u8 = 1 << 2;
+const NVME_TELEMETRY_REASON_VVU: u8 = 1 << 3;
+
+/// Telemetry log page type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NvmeTelemetryType {
+    /// Telemetry Host-Initiated log page (07h), new data is captured by
+    /// controller on request.
+    Host,
+    /// Telemetry Controller-Initiated log page (08h), data captured by
+    /// controller on its own, e.g. on internal error.
+    Controller,
+}
+
+impl NvmeTelemetryType {
+    pub(crate) fn lid(&self) -> u8 {
+        match *self {
+            NvmeTelemetryType::Host => NVME_LOG_TELEMETRY_HOST,
+            NvmeTelemetryType::Controller => NVME_LOG_TELEMETRY_CTRL,
+        }
+    }
+}
+
+impl fmt::Display for NvmeTelemetryType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            NvmeTelemetryType::Host => write!(f, "host-initiated"),
+            NvmeTelemetryType::Controller =>
+                write!(f, "controller-initiated"),
+        }
+    }
+}
+
+/// Reason Identifier of telemetry log page, the fields are vendor
+/// specific and only present when indicated valid by controller.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NvmeTelemetryReason {
+    pub error_id:               Option<String>,
+    pub file_id:                Option<String>,
+    pub line_number:            Option<u16>,
+    pub vu_extension:           Option<Vec<u8>>,
+    /// The whole 128 bytes of Reason Identifier.
+    pub raw:                    Vec<u8>,
+}
+
+impl NvmeTelemetryReason {
+    fn parse(data: &[u8]) -> NvmeTelemetryReason {
+        let flags = data[74];
+        NvmeTelemetryReason {
+            error_id:           if flags & NVME_TELEMETRY_REASON_VEI != 0 {
+                                    Some(ascii_padded_to_string(&data[0..64]))
+                                } else {
+                                    None
+                                },
+            file_id:            if flags & NVME_TELEMETRY_REASON_VFID != 0 {
+                                    Some(ascii_padded_to_string(&data[64..72]))
+                                } else {
+                                    None
+                                },
+            line_number:        if flags & NVME_TELEMETRY_REASON_VLN != 0 {
+                                    Some(to_u16([data[72], data[73]]))
+                                } else {
+                                    None
+                                },
+            vu_extension:       if flags & NVME_TELEMETRY_REASON_VVU != 0 {
+                                    Some(data[96..128].to_vec())
+                                } else {
+                                    None
+                                },
+            raw:                data.to_vec(),
+        }
+    }
+}
+
+/// Header (block 0) of telemetry log page.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NvmeTelemetryHeader {
+    pub lid:                    u8,
+    pub ieee_oui:               u32,
+    /// Last block of data area 1, 2, 3 and 4, block 0 is this header.
+    pub data_area_last_blocks:  [u32; 4],
+    /// Controller-initiated telemetry data is available.
+    pub ctrl_data_available:    bool,
+    /// Generation number of host-initiated telemetry data.
+    pub host_gen:               u8,
+    /// Generation number of controller-initiated telemetry data.
+    pub ctrl_gen:               u8,
+    pub reason:                 NvmeTelemetryReason,
+}
+
+impl NvmeTelemetryHeader {
+    /// Length in bytes of telemetry log page up to the end of data area
+    /// `data_area`, including this header.
+    pub fn len_get(&self, data_area: u8) -> usize {
+        let last_block = self.data_area_last_blocks.iter()
+            .take(data_area as usize)
+            .max()
+            .cloned()
+            .unwrap_or(0);
+        (last_block as usize + 1) * NVME_TELEMETRY_BLOCK_LEN
+    }
+
+    /* Generation number of telemetry data of specified type */
+    pub(crate) fn gen_get(&self, kind: NvmeTelemetryType) -> u8 {
+        match kind {
+            NvmeTelemetryType::Host => self.host_gen,
+            NvmeTelemetryType::Controller => self.ctrl_gen,
+        }
+    }
+
+    pub(crate) fn parse(data: &[u8], kind: NvmeTelemetryType)
+        -> Result<NvmeTelemetryHeader> {
+        if data.len() < NVME_TELEMETRY_BLOCK_LEN {
+            return Err(ErrorKind::CorruptedData(
+                "Telemetry log page header is truncated".to_string()).into());
+        }
+        if data[0] != kind.lid() {
+            return Err(ErrorKind::CorruptedData(format!(
+                "Telemetry log page header holds log identifier 0x{:02x}, \
+                 expecting 0x{:02x}", data[0], kind.lid())).into());
+        }
+        Ok(NvmeTelemetryHeader {
+            lid:                data[0],
+            ieee_oui:           to_u32([data[5], data[6], data[7], 0]),
+            data_area_last_blocks: [
+                u32::from(to_u16([data[8], data[9]])),
+                u32::from(to_u16([data[10], data[11]])),
+                u32::from(to_u16([data[12], data[13]])),
+                to_u32([data[16], data[17], data[18], data[19]]),
+            ],
+            ctrl_data_available: data[382] != 0,
+            host_gen:           data[381],
+            ctrl_gen:           data[383],
+            reason:             NvmeTelemetryReason::parse(
+                &data[NVME_TELEMETRY_REASON_OFFSET..
+                      NVME_TELEMETRY_REASON_OFFSET +
+                      NVME_TELEMETRY_REASON_LEN]),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header_build(kind: NvmeTelemetryType) -> Vec<u8> {
+        let mut data = vec![0u8; NVME_TELEMETRY_BLOCK_LEN];
+        data[0] = kind.lid();
+        data[5..8].copy_from_slice(&[0x11, 0x22, 0x33]);
+        data[8..10].copy_from_slice(&3u16.to_le_bytes());
+        data[10..12].copy_from_slice(&5u16.to_le_bytes());
+        data[12..14].copy_from_slice(&5u16.to_le_bytes());
+        data[16..20].copy_from_slice(&0x1_0000u32.to_le_bytes());
+        data[381] = 2;
+        data[382] = 1;
+        data[383] = 4;
+        data
+    }
+
+    #[test]
+    fn test_header_parse() {
+        let mut data = header_build(NvmeTelemetryType::Host);
+        let reason = &mut data[NVME_TELEMETRY_REASON_OFFSET..];
+        reason[..64].copy_from_slice(&[b' '; 64]);
+        reason[..4].copy_from_slice(b"ERR1");
+        reason[64..70].copy_from_slice(b"main.c");
+        reason[72..74].copy_from_slice(&0x1234u16.to_le_bytes());
+        reason[74] = NVME_TELEMETRY_REASON_VEI | NVME_TELEMETRY_REASON_VFID |
+            NVME_TELEMETRY_REASON_VLN;
+        reason[96] = 0xaa;
+        let header = NvmeTelemetryHeader::parse(
+            &data, NvmeTelemetryType::Host).unwrap();
+
+        assert_eq!(header.lid, NVME_LOG_TELEMETRY_HOST);
+        assert_eq!(header.ieee_oui, 0x33_2211);
+        assert_eq!(header.data_area_last_blocks, [3, 5, 5, 0x1_0000]);
+        assert_eq!(header.len_get(1), 4 * NVME_TELEMETRY_BLOCK_LEN);
+        assert_eq!(header.len_get(3), 6 * NVME_TELEMETRY_BLOCK_LEN);
+        assert_eq!(header.len_get(4),
+                   0x1_0001 * NVME_TELEMETRY_BLOCK_LEN);
+        assert!(header.ctrl_data_available);
+        assert_eq!(header.gen_get(NvmeTelemetryType::Host), 2);
+        assert_eq!(header.gen_get(NvmeTelemetryType::Controller), 4);
+
+        let reason = &header.reason;
+        assert_eq!(reason.error_id.as_deref(), Some("ERR1"));
+        assert_eq!(reason.file_id.as_deref(), Some("main.c"));
+        assert_eq!(reason.line_number, Some(0x1234));
+        /* VVU not set */
+        assert_eq!(reason.vu_extension, None);
+        assert_eq!(reason.raw.len(), NVME_TELEMETRY_REASON_LEN);
+    }
+
+    #[test]
+    fn test_header_parse_empty() {
+        let mut data = vec![0u8; NVME_TELEMETRY_BLOCK_LEN];
+        data[0] = NVME_LOG_TELEMETRY_CTRL;
+        let header = NvmeTelemetryHeader::parse(
+            &data, NvmeTelemetryType::Controller).unwrap();
+        assert_eq!(header.data_area_last_blocks, [0; 4]);
+        assert_eq!(header.len_get(4), NVME_TELEMETRY_BLOCK_LEN);
+        assert_eq!(header.len_get(0), NVME_TELEMETRY_BLOCK_LEN);
+        assert!(!header.ctrl_data_available);
+        assert_eq!(header.reason, NvmeTelemetryReason {
+            raw:                vec![0u8; NVME_TELEMETRY_REASON_LEN],
+            ..                  Default::default()
+        });
+    }
+
+    #[test]
+    fn test_header_parse_invalid() {
+        let data = header_build(NvmeTelemetryType::Host);
+        assert!(NvmeTelemetryHeader::parse(
+            &data, NvmeTelemetryType::Controller).is_err());
+        for len in &[0, 1, NVME_TELEMETRY_BLOCK_LEN - 1] {
+            assert!(NvmeTelemetryHeader::parse(
+                &data[..*len], NvmeTelemetryType::Host).is_err());
+        }
+    }
+}
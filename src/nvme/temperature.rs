@@ -0,0 +1,155 @@
+/*
+ * Copyright (C) 2017 Red Hat, Inc.
+ * This library is free software; you can redistribute it and/or
+ * modify it under the terms of the GNU Lesser General Public
+ * License as published by the Free Software Foundation; either
+ * version 2.1 of the License, or (at your option) any later version.
+ *
+ * This library is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+ * Lesser General Public License for more details.
+ *
+ * You should have received a copy of the GNU Lesser General Public
+ * License along with this library; If not, see <http://www.gnu.org/licenses/>.
+ *
+ * Author: Gris Ge <fge@redhat.com>
+ */
+
+use std::path::Path;
+
+use super::error::*;
+use super::utils::{dir_entry_names_get, sysfs_read_str, to_u16};
+
+pub(crate) const NVME_SMART_LOG_LEN: usize = 512;
+const NVME_SMART_CRIT_WARN_TEMP: u8 = 1 << 1;
+const NVME_SMART_COMPOSITE_TEMP_OFFSET: usize = 1;
+const NVME_SMART_TEMP_SENSOR_OFFSET: usize = 200;
+const NVME_SMART_TEMP_SENSOR_COUNT: usize = 8;
+
+/* Same as kernel hwmon, 0 Celsius is 273.15 Kelvin */
+const KELVIN_ZERO_CELSIUS: f64 = 273.15;
+
+pub(crate) fn kelvin_to_celsius(kelvin: u16) -> f64 {
+    f64::from(kelvin) - KELVIN_ZERO_CELSIUS
+}
+
+/// Temperature sensor of NVMe controller. `Composite` is the controller
+/// composite temperature, `Sensor N` are the optional sensors.
+#[derive(Debug, Clone)]
+pub struct NvmeTemperature {
+    label:                      String,
+    celsius:                    f64,
+    min_celsius:                Option<f64>,
+    max_celsius:                Option<f64>,
+    alarm:                      Option<bool>,
+}
+
+impl NvmeTemperature {
+    pub fn label_get(&self) -> &str { &self.label }
+    pub fn celsius_get(&self) -> f64 { self.celsius }
+    /// Lower threshold, None if not available.
+    pub fn min_celsius_get(&self) -> Option<f64> { self.min_celsius }
+    /// Upper threshold, None if not available.
+    pub fn max_celsius_get(&self) -> Option<f64> { self.max_celsius }
+    /// Whether temperature exceeds threshold, None if not available.
+    pub fn alarm_get(&self) -> Option<bool> { self.alarm }
+
+    /* Read hwmon sensors under `ctrl_sysfs_path`, linux kernel 5.5+
+     * registers hwmon device under PCI device, newer kernels under the
+     * controller device. Return empty if no hwmon device.
+     */
+    pub(crate) fn from_hwmon(ctrl_sysfs_path: &str)
+        -> Result<Vec<NvmeTemperature>> {
+        let mut ret = Vec::new();
+        let hwmon_dir = match hwmon_dir_find(ctrl_sysfs_path) {
+            Some(d) => d,
+            None => return Ok(ret),
+        };
+        for entry in dir_entry_names_get(&hwmon_dir)? {
+            if !entry.starts_with("temp") || !entry.ends_with("_input") {
+                continue;
+            }
+            let prefix = format!("{}/{}", hwmon_dir,
+                                 entry.trim_end_matches("_input"));
+            let millidegree = |name: &str| {
+                sysfs_read_str(&format!("{}_{}", prefix, name)).ok()
+                    .and_then(|v| v.parse::<i64>().ok())
+                    .map(|v| v as f64 / 1000f64)
+            };
+            let celsius = match millidegree("input") {
+                Some(c) => c,
+                /* Sensor might report -ENODATA */
+                None => continue,
+            };
+            ret.push(NvmeTemperature {
+                label:          sysfs_read_str(&format!("{}_label", prefix))
+                                    .unwrap_or_default(),
+                celsius,
+                min_celsius:    millidegree("min"),
+                max_celsius:    millidegree("max"),
+                alarm:          sysfs_read_str(&format!("{}_alarm", prefix))
+                                    .ok().map(|a| a != "0"),
+            });
+        }
+        Ok(ret)
+    }
+
+    /* Parse SMART / Health Information log page. Warning composite
+     * temperature threshold (WCTEMP in Kelvin, 0 if not reported) is used
+     * as max of composite temperature.
+     */
+    pub(crate) fn from_smart_log(data: &[u8], wctemp: u16)
+        -> Result<Vec<NvmeTemperature>> {
+        if data.len() < NVME_SMART_LOG_LEN {
+            return Err(ErrorKind::CorruptedData(format!(
+                "SMART log page should be {} bytes, got {}",
+                NVME_SMART_LOG_LEN, data.len())).into());
+        }
+        let mut ret = Vec::new();
+        let off = NVME_SMART_COMPOSITE_TEMP_OFFSET;
+        ret.push(NvmeTemperature {
+            label:              "Composite".to_string(),
+            celsius:            kelvin_to_celsius(
+                                    to_u16([data[off], data[off + 1]])),
+            min_celsius:        None,
+            max_celsius:        if wctemp != 0 {
+                                    Some(kelvin_to_celsius(wctemp))
+                                } else {
+                                    None
+                                },
+            alarm:              Some(data[0] & NVME_SMART_CRIT_WARN_TEMP != 0),
+        });
+        for i in 0..NVME_SMART_TEMP_SENSOR_COUNT {
+            let off = NVME_SMART_TEMP_SENSOR_OFFSET + i * 2;
+            let kelvin = to_u16([data[off], data[off + 1]]);
+            /* Zero means sensor not implemented */
+            if kelvin == 0 {
+                continue;
+            }
+            ret.push(NvmeTemperature {
+                label:          format!("Sensor {}", i + 1),
+                celsius:        kelvin_to_celsius(kelvin),
+                min_celsius:    None,
+                max_celsius:    None,
+                alarm:          None,
+            });
+        }
+        Ok(ret)
+    }
+}
+
+fn hwmon_dir_find(ctrl_sysfs_path: &str) -> Option<String> {
+    for parent in &[ctrl_sysfs_path.to_string(),
+                    format!("{}/device/hwmon", ctrl_sysfs_path)] {
+        if !Path::new(parent).is_dir() {
+            continue;
+        }
+        for entry in dir_entry_names_get(parent).unwrap_or_default() {
+            if entry.starts_with("hwmon") {
+                return Some(format!("{}/{}", parent, entry));
+            }
+        }
+    }
+    None
+}
@@ -0,0 +1,811 @@
+/*
+ * Copyright (C) 2017 Red Hat, Inc.
+ * This library is free software; you can redistribute it and/or
+ * modify it under the terms of the GNU Lesser General Public
+ * License as published by the Free Software Foundation; either
+ * version 2.1 of the License, or (at your option) any later version.
+ *
+ * This library is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+ * Lesser General Public License for more details.
+ *
+ * You should have received a copy of the GNU Lesser General Public
+ * License along with this library; If not, see <http://www.gnu.org/licenses/>.
+ *
+ * Author: Gris Ge <fge@redhat.com>
+ */
+
+use std::fs::File;
+use std::io;
+use std::mem::forget;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::ptr;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use super::cmd_opts::{NvmeCmdOpts, NvmeCmdOptsTable};
+use super::error::*;
+use super::ioctl::{nvme_cmd_errno_error, nvme_ioctl_fd_open};
+use super::passthru::{passthru_check, NvmeCmd, NvmeCmdTransport,
+                      NvmeCompletion, NvmeDataDirection};
+use super::utils::mutex_lock;
+
+const IORING_SETUP_SQE128: u32 = 1 << 10;
+const IORING_SETUP_CQE32: u32 = 1 << 11;
+const IORING_OFF_SQ_RING: libc::off_t = 0;
+const IORING_OFF_CQ_RING: libc::off_t = 0x8000000;
+const IORING_OFF_SQES: libc::off_t = 0x10000000;
+const IORING_ENTER_GETEVENTS: u32 = 1;
+const IORING_OP_URING_CMD: u8 = 46;
+
+const IO_URING_SQE128_LEN: usize = 128;
+const IO_URING_CQE32_LEN: usize = 32;
+
+/* Offsets in submission queue entry */
+const SQE_OPCODE_OFFSET: usize = 0;
+const SQE_FD_OFFSET: usize = 4;
+const SQE_CMD_OP_OFFSET: usize = 8;
+const SQE_USER_DATA_OFFSET: usize = 32;
+const SQE_CMD_OFFSET: usize = 48;
+
+/* Offsets in completion queue entry */
+const CQE_USER_DATA_OFFSET: usize = 0;
+const CQE_RES_OFFSET: usize = 8;
+const CQE_BIG_CQE_OFFSET: usize = 16;
+
+/* _IOWR('N', 0x80, struct nvme_uring_cmd) and _IOWR('N', 0x82, ...) */
+const NVME_URING_CMD_IO: u32 = 0xc048_4e80;
+const NVME_URING_CMD_ADMIN: u32 = 0xc048_4e82;
+/* Size of `struct nvme_uring_cmd` */
+const NVME_URING_CMD_LEN: usize = 72;
+
+#[repr(C)]
+#[derive(Default)]
+struct IoSqringOffsets {
+    head:                       u32,
+    tail:                       u32,
+    ring_mask:                  u32,
+    ring_entries:               u32,
+    flags:                      u32,
+    dropped:                    u32,
+    array:                      u32,
+    resv1:                      u32,
+    user_addr:                  u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct IoCqringOffsets {
+    head:                       u32,
+    tail:                       u32,
+    ring_mask:                  u32,
+    ring_entries:               u32,
+    overflow:                   u32,
+    cqes:                       u32,
+    flags:                      u32,
+    resv1:                      u32,
+    user_addr:                  u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+// Copy from /usr/include/linux/io_uring.h
+struct IoUringParams {
+    sq_entries:                 u32,
+    cq_entries:                 u32,
+    flags:                      u32,
+    sq_thread_cpu:              u32,
+    sq_thread_idle:             u32,
+    features:                   u32,
+    wq_fd:                      u32,
+    resv:                       [u32; 3],
+    sq_off:                     IoSqringOffsets,
+    cq_off:                     IoCqringOffsets,
+}
+
+/// Request of `NvmeUringTransport::submit_batch()`. The data buffer is
+/// owned by request so it stays valid until the command completes.
+#[derive(Debug)]
+pub struct NvmeUringRequest {
+    pub is_admin:               bool,
+    pub cmd:                    NvmeCmd,
+    pub data:                   Vec<u8>,
+    /// Completion of command, None before completion. Error if kernel
+    /// failed to issue the command.
+    pub result:                 Option<Result<NvmeCompletion>>,
+}
+
+impl NvmeUringRequest {
+    pub fn admin(cmd: NvmeCmd, data: Vec<u8>) -> NvmeUringRequest {
+        NvmeUringRequest {
+            is_admin:           true,
+            cmd,
+            data,
+            result:             None,
+        }
+    }
+
+    pub fn io(cmd: NvmeCmd, data: Vec<u8>) -> NvmeUringRequest {
+        NvmeUringRequest {
+            is_admin:           false,
+            cmd,
+            data,
+            result:             None,
+        }
+    }
+}
+
+/// io_uring transport submitting `IORING_OP_URING_CMD` to NVMe generic
+/// character device `/dev/ngXnY` or controller character device
+/// `/dev/nvmeX` (admin commands only). Requires linux kernel 5.19+.
+/// Transport opened from controller or namespace applies the opcode
+/// policy of that controller to every command.
+pub struct NvmeUringTransport {
+    dev:                        File,
+    ring:                       Mutex<Uring>,
+    opts:                       Arc<NvmeCmdOptsTable>,
+}
+
+impl NvmeUringTransport {
+    /// Open `dev_path` with a ring of `depth` submission queue entries.
+    pub fn open(dev_path: &str, depth: u32) -> Result<NvmeUringTransport> {
+        NvmeUringTransport::open_with_opts(
+            dev_path, depth, Arc::new(NvmeCmdOptsTable::default()))
+    }
+
+    /* Share command options and opcode policy of controller */
+    pub(crate) fn open_with_opts(dev_path: &str, depth: u32,
+                                 opts: Arc<NvmeCmdOptsTable>)
+        -> Result<NvmeUringTransport> {
+        Ok(NvmeUringTransport {
+            dev:                nvme_ioctl_fd_open(dev_path)?,
+            ring:               Mutex::new(Uring::new(depth)?),
+            opts,
+        })
+    }
+
+    /// Submit all requests and wait for their completion, up to ring depth
+    /// of commands are in flight at the same time. Timeout and retry of
+    /// command options apply the same way as ioctl passthrough. The
+    /// per-command result is stored in `NvmeUringRequest.result`, request
+    /// denied by opcode
+    /// policy or with buffer not matching the transfer direction of opcode
+    /// is not submitted and gets the error there. Error is only returned
+    /// when the ring itself failed, the ring is unusable afterwards.
+    pub fn submit_batch(&self, reqs: Vec<NvmeUringRequest>)
+        -> Result<Vec<NvmeUringRequest>> {
+        let mut ring = mutex_lock(&self.ring);
+        uring_batch_run(&mut *ring, self.dev.as_raw_fd(), &self.opts, reqs)
+    }
+
+    fn cmd_run(&self, is_admin: bool, cmd: &NvmeCmd, data: &mut [u8])
+        -> Result<NvmeCompletion> {
+        let req = NvmeUringRequest {
+            is_admin,
+            cmd:                *cmd,
+            data:               data.to_vec(),
+            result:             None,
+        };
+        let mut req = match self.submit_batch(vec![req])?.pop() {
+            Some(r) => r,
+            None => return Err(ErrorKind::LibBug(
+                "io_uring batch lost the request".to_string()).into()),
+        };
+        data.copy_from_slice(&req.data);
+        match req.result.take() {
+            Some(r) => r,
+            None => Err(ErrorKind::LibBug(
+                "io_uring request not completed".to_string()).into()),
+        }
+    }
+}
+
+impl NvmeCmdTransport for NvmeUringTransport {
+    fn admin_cmd(&self, cmd: &NvmeCmd, data: &mut [u8])
+        -> Result<NvmeCompletion> {
+        self.cmd_run(true, cmd, data)
+    }
+
+    fn io_cmd(&self, cmd: &NvmeCmd, data: &mut [u8])
+        -> Result<NvmeCompletion> {
+        self.cmd_run(false, cmd, data)
+    }
+}
+
+/* Submission and completion of requests, mocked by unit test */
+trait UringRing {
+    /* Submit requests without result yet and wait for their completion */
+    fn run(&mut self, dev_fd: RawFd, reqs: &mut [NvmeUringRequest],
+           opts: &NvmeCmdOptsTable) -> Result<()>;
+    /* Number of submitted commands not completed yet */
+    fn inflight(&self) -> usize;
+}
+
+/* Run requests allowed by opcode policy on `ring` and resubmit the failed
+ * ones allowed to retry by command options.
+ */
+fn uring_batch_run(ring: &mut dyn UringRing, dev_fd: RawFd,
+                   opts: &NvmeCmdOptsTable,
+                   mut reqs: Vec<NvmeUringRequest>)
+    -> Result<Vec<NvmeUringRequest>> {
+    let policy = opts.policy_get();
+    for req in reqs.iter_mut() {
+        let dir = NvmeDataDirection::from_opcode(req.cmd.opcode)
+            .unwrap_or(NvmeDataDirection::None);
+        req.result = match passthru_check(&policy, req.is_admin,
+                                          &req.cmd, dir, req.data.len(),
+                                          0) {
+            Ok(()) => None,
+            Err(e) => Some(Err(e)),
+        };
+    }
+    let mut attempts = vec![0u32; reqs.len()];
+    loop {
+        if let Err(e) = ring.run(dev_fd, &mut reqs, opts) {
+            /* Kernel might still write to the buffers of in-flight
+             * commands, leak them instead of freeing.
+             */
+            if ring.inflight() != 0 {
+                forget(reqs);
+            }
+            return Err(e);
+        }
+        /* Resubmit failed commands allowed to retry by command options
+         * after the longest of their delays.
+         */
+        let mut delay = None;
+        for (req, attempt) in reqs.iter_mut().zip(attempts.iter_mut()) {
+            let status = match req.result {
+                Some(Ok(ref c)) if !c.is_success() => c.status,
+                _ => continue,
+            };
+            let cmd_opts = opts.get(req.is_admin, req.cmd.opcode);
+            if let Some(d) = opts.retry_delay(&cmd_opts, status, *attempt) {
+                *attempt += 1;
+                req.result = None;
+                delay = Some(delay.map_or(d, |m: Duration| m.max(d)));
+            }
+        }
+        match delay {
+            Some(d) => thread::sleep(d),
+            None => return Ok(reqs),
+        }
+    }
+}
+
+/* Timeout of command options applies when command has none */
+fn uring_cmd_timeout_ms(cmd: &NvmeCmd, opts: &NvmeCmdOpts) -> u32 {
+    if cmd.timeout_ms != 0 {
+        cmd.timeout_ms
+    } else {
+        opts.timeout_ms()
+    }
+}
+
+/* Encode `struct nvme_uring_cmd` into the command area of 128 bytes
+ * submission queue entry.
+ */
+fn uring_sqe_encode(sqe: &mut [u8], dev_fd: RawFd,
+                    req: &mut NvmeUringRequest, opts: &NvmeCmdOpts,
+                    user_data: u64) {
+    for b in sqe.iter_mut() {
+        *b = 0;
+    }
+    let cmd_op = if req.is_admin {
+        NVME_URING_CMD_ADMIN
+    } else {
+        NVME_URING_CMD_IO
+    };
+    sqe[SQE_OPCODE_OFFSET] = IORING_OP_URING_CMD;
+    sqe[SQE_FD_OFFSET..SQE_FD_OFFSET + 4]
+        .copy_from_slice(&dev_fd.to_ne_bytes());
+    sqe[SQE_CMD_OP_OFFSET..SQE_CMD_OP_OFFSET + 4]
+        .copy_from_slice(&cmd_op.to_ne_bytes());
+    sqe[SQE_USER_DATA_OFFSET..SQE_USER_DATA_OFFSET + 8]
+        .copy_from_slice(&user_data.to_ne_bytes());
+
+    let addr = if req.data.is_empty() {
+        0
+    } else {
+        req.data.as_mut_ptr() as u64
+    };
+    let cmd = &req.cmd;
+    let timeout_ms = uring_cmd_timeout_ms(cmd, opts);
+    let nvme_cmd = &mut sqe[SQE_CMD_OFFSET..SQE_CMD_OFFSET +
+                            NVME_URING_CMD_LEN];
+    nvme_cmd[0] = cmd.opcode;
+    nvme_cmd[1] = cmd.flags;
+    let dwords: [(usize, u32); 12] = [
+        (4, cmd.nsid), (8, cmd.cdw2), (12, cmd.cdw3),
+        (36, req.data.len() as u32),
+        (40, cmd.cdw10), (44, cmd.cdw11), (48, cmd.cdw12),
+        (52, cmd.cdw13), (56, cmd.cdw14), (60, cmd.cdw15),
+        (64, timeout_ms), (68, 0),
+    ];
+    for &(off, value) in dwords.iter() {
+        nvme_cmd[off..off + 4].copy_from_slice(&value.to_ne_bytes());
+    }
+    nvme_cmd[24..32].copy_from_slice(&addr.to_ne_bytes());
+}
+
+/* Decode 32 bytes completion queue entry into user data and command
+ * completion. Negative `res` is errno, positive is NVMe status.
+ */
+fn uring_cqe_decode(cqe: &[u8])
+    -> (u64, ::std::result::Result<NvmeCompletion, i32>) {
+    let mut u64_bytes = [0u8; 8];
+    let mut u32_bytes = [0u8; 4];
+    u64_bytes.copy_from_slice(
+        &cqe[CQE_USER_DATA_OFFSET..CQE_USER_DATA_OFFSET + 8]);
+    let user_data = u64::from_ne_bytes(u64_bytes);
+    u32_bytes.copy_from_slice(&cqe[CQE_RES_OFFSET..CQE_RES_OFFSET + 4]);
+    let res = i32::from_ne_bytes(u32_bytes);
+    u64_bytes.copy_from_slice(
+        &cqe[CQE_BIG_CQE_OFFSET..CQE_BIG_CQE_OFFSET + 8]);
+    let result = u64::from_ne_bytes(u64_bytes) as u32;
+
+    let ret = if res < 0 {
+        Err(-res)
+    } else {
+        Ok(NvmeCompletion {
+            result,
+            status:             res as u16,
+        })
+    };
+    (user_data, ret)
+}
+
+/* Store completion of `cqe` into the request it belongs to, errno is
+ * mapped the same way as ioctl passthrough.
+ */
+fn uring_cqe_complete(cqe: &[u8], reqs: &mut [NvmeUringRequest],
+                      opts: &NvmeCmdOptsTable) -> Result<()> {
+    let (user_data, result) = uring_cqe_decode(cqe);
+    let req = match reqs.get_mut(user_data as usize) {
+        Some(r) => r,
+        None => return Err(ErrorKind::LibBug(format!(
+            "Got unknown io_uring user data {}", user_data)).into()),
+    };
+    let opcode = req.cmd.opcode;
+    let timeout_ms = uring_cmd_timeout_ms(
+        &req.cmd, &opts.get(req.is_admin, opcode));
+    req.result = Some(result.map_err(
+        |errno| nvme_cmd_errno_error(errno, opcode, timeout_ms)));
+    Ok(())
+}
+
+struct UringMmap {
+    ptr:                        *mut u8,
+    len:                        usize,
+}
+
+impl UringMmap {
+    fn new(ring_fd: RawFd, len: usize, offset: libc::off_t)
+        -> Result<UringMmap> {
+        let ptr = unsafe {
+            libc::mmap(ptr::null_mut(), len,
+                       libc::PROT_READ | libc::PROT_WRITE,
+                       libc::MAP_SHARED | libc::MAP_POPULATE, ring_fd, offset)
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error().into());
+        }
+        Ok(UringMmap {
+            ptr:                ptr as *mut u8,
+            len,
+        })
+    }
+
+    fn atomic_u32(&self, offset: u32) -> &AtomicU32 {
+        unsafe { &*(self.ptr.add(offset as usize) as *const AtomicU32) }
+    }
+
+    fn slice(&self, offset: usize, len: usize) -> &[u8] {
+        unsafe { ::std::slice::from_raw_parts(self.ptr.add(offset), len) }
+    }
+
+    fn slice_mut(&mut self, offset: usize, len: usize) -> &mut [u8] {
+        unsafe { ::std::slice::from_raw_parts_mut(self.ptr.add(offset), len) }
+    }
+}
+
+impl Drop for UringMmap {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.ptr as *mut libc::c_void, self.len);
+        }
+    }
+}
+
+struct Uring {
+    sq_ring:                    UringMmap,
+    cq_ring:                    UringMmap,
+    sqes:                       UringMmap,
+    params:                     IoUringParams,
+    inflight:                   usize,
+    broken:                     bool,
+    // Dropped after the mmaps.
+    ring_fd:                    File,
+}
+
+/* The mmaps are only accessed with the Mutex of NvmeUringTransport held */
+unsafe impl Send for Uring {}
+
+impl Uring {
+    fn new(depth: u32) -> Result<Uring> {
+        let mut params = IoUringParams {
+            flags:              IORING_SETUP_SQE128 | IORING_SETUP_CQE32,
+            ..                  Default::default()
+        };
+        let fd = unsafe {
+            libc::syscall(libc::SYS_io_uring_setup, depth,
+                          &mut params as *mut IoUringParams)
+        };
+        if fd < 0 {
+            return Err(ErrorKind::NotSupported(format!(
+                "Failed to setup io_uring with 128 bytes SQE: {}",
+                io::Error::last_os_error())).into());
+        }
+        let ring_fd = unsafe { File::from_raw_fd(fd as RawFd) };
+        let raw_fd = ring_fd.as_raw_fd();
+        let sq_len = params.sq_off.array as usize +
+            params.sq_entries as usize * 4;
+        let cq_len = params.cq_off.cqes as usize +
+            params.cq_entries as usize * IO_URING_CQE32_LEN;
+        let sqes_len = params.sq_entries as usize * IO_URING_SQE128_LEN;
+        Ok(Uring {
+            sq_ring:            UringMmap::new(raw_fd, sq_len,
+                                               IORING_OFF_SQ_RING)?,
+            cq_ring:            UringMmap::new(raw_fd, cq_len,
+                                               IORING_OFF_CQ_RING)?,
+            sqes:               UringMmap::new(raw_fd, sqes_len,
+                                               IORING_OFF_SQES)?,
+            params,
+            inflight:           0,
+            broken:             false,
+            ring_fd,
+        })
+    }
+
+    /* Submit entries till `sq_tail` and wait for at least one completion.
+     * Entries might be partially consumed by kernel when interrupted.
+     */
+    fn enter(&self, sq_tail: u32) -> Result<()> {
+        loop {
+            let to_submit = sq_tail.wrapping_sub(
+                self.sq_ring.atomic_u32(self.params.sq_off.head)
+                    .load(Ordering::Acquire));
+            let rc = unsafe {
+                libc::syscall(libc::SYS_io_uring_enter,
+                              self.ring_fd.as_raw_fd(), to_submit, 1u32,
+                              IORING_ENTER_GETEVENTS,
+                              ptr::null::<libc::sigset_t>(), 0usize)
+            };
+            if rc >= 0 {
+                return Ok(());
+            }
+            let e = io::Error::last_os_error();
+            match e.raw_os_error() {
+                Some(libc::EINTR) | Some(libc::EAGAIN) | Some(libc::EBUSY) =>
+                    continue,
+                _ => return Err(ErrorKind::CommandFailed(format!(
+                    "io_uring_enter failed: {}", e)).into()),
+            }
+        }
+    }
+}
+
+impl UringRing for Uring {
+    fn run(&mut self, dev_fd: RawFd, reqs: &mut [NvmeUringRequest],
+           opts: &NvmeCmdOptsTable) -> Result<()> {
+        if self.broken {
+            return Err(ErrorKind::InvalidArgument(
+                "io_uring is unusable after previous failure".to_string())
+                .into());
+        }
+        let sq_entries = self.params.sq_entries as usize;
+        let sq_mask = self.sq_ring.atomic_u32(self.params.sq_off.ring_mask)
+            .load(Ordering::Relaxed);
+        let cq_mask = self.cq_ring.atomic_u32(self.params.cq_off.ring_mask)
+            .load(Ordering::Relaxed);
+        let mut next = 0;
+        let mut done = 0;
+
+        while done < reqs.len() {
+            /* We are the only producer of submission queue */
+            let sq_tail = self.sq_ring.atomic_u32(self.params.sq_off.tail);
+            let mut tail = sq_tail.load(Ordering::Relaxed);
+            while next < reqs.len() && self.inflight < sq_entries {
+                /* Request refused or completed without retry */
+                if reqs[next].result.is_some() {
+                    next += 1;
+                    done += 1;
+                    continue;
+                }
+                let index = tail & sq_mask;
+                let sqe = self.sqes.slice_mut(
+                    index as usize * IO_URING_SQE128_LEN,
+                    IO_URING_SQE128_LEN);
+                let req = &mut reqs[next];
+                let cmd_opts = opts.get(req.is_admin, req.cmd.opcode);
+                uring_sqe_encode(sqe, dev_fd, req, &cmd_opts, next as u64);
+                self.sq_ring.atomic_u32(self.params.sq_off.array + index * 4)
+                    .store(index, Ordering::Relaxed);
+                tail = tail.wrapping_add(1);
+                sq_tail.store(tail, Ordering::Release);
+                next += 1;
+                self.inflight += 1;
+            }
+            if self.inflight == 0 {
+                continue;
+            }
+            if let Err(e) = self.enter(tail) {
+                self.broken = true;
+                return Err(e);
+            }
+
+            let cq_head = self.cq_ring.atomic_u32(self.params.cq_off.head);
+            let cq_tail = self.cq_ring.atomic_u32(self.params.cq_off.tail)
+                .load(Ordering::Acquire);
+            let mut head = cq_head.load(Ordering::Relaxed);
+            while head != cq_tail {
+                let cqe = self.cq_ring.slice(
+                    self.params.cq_off.cqes as usize +
+                    (head & cq_mask) as usize * IO_URING_CQE32_LEN,
+                    IO_URING_CQE32_LEN);
+                if let Err(e) = uring_cqe_complete(cqe, reqs, opts) {
+                    self.broken = true;
+                    return Err(e);
+                }
+                head = head.wrapping_add(1);
+                self.inflight -= 1;
+                done += 1;
+            }
+            cq_head.store(head, Ordering::Release);
+        }
+        Ok(())
+    }
+
+    fn inflight(&self) -> usize {
+        self.inflight
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::passthru::NvmeOpcodePolicy;
+
+    fn u32_at(buf: &[u8], off: usize) -> u32 {
+        let mut bytes = [0u8; 4];
+        bytes.copy_from_slice(&buf[off..off + 4]);
+        u32::from_ne_bytes(bytes)
+    }
+
+    fn u64_at(buf: &[u8], off: usize) -> u64 {
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(&buf[off..off + 8]);
+        u64::from_ne_bytes(bytes)
+    }
+
+    fn cqe_build(user_data: u64, res: i32, result: u32) -> Vec<u8> {
+        let mut cqe = vec![0u8; IO_URING_CQE32_LEN];
+        cqe[0..8].copy_from_slice(&user_data.to_ne_bytes());
+        cqe[8..12].copy_from_slice(&res.to_ne_bytes());
+        cqe[16..24].copy_from_slice(&u64::from(result).to_ne_bytes());
+        cqe
+    }
+
+    #[test]
+    fn test_sqe_encode_admin() {
+        let mut sqe = vec![0xffu8; IO_URING_SQE128_LEN];
+        let mut req = NvmeUringRequest::admin(NvmeCmd {
+            opcode:             0x06,
+            flags:              0x01,
+            nsid:               0xffff_ffff,
+            cdw10:              0x1,
+            cdw11:              0x2 << 24,
+            ..                  Default::default()
+        }, vec![0u8; 4096]);
+        let opts = NvmeCmdOpts {
+            timeout:            Some(Duration::from_secs(5)),
+            ..                  Default::default()
+        };
+        uring_sqe_encode(&mut sqe, 9, &mut req, &opts, 0x1234);
+        let addr = req.data.as_ptr() as u64;
+
+        assert_eq!(sqe[0], IORING_OP_URING_CMD);
+        assert_eq!(u32_at(&sqe, 4), 9);
+        assert_eq!(u32_at(&sqe, 8), NVME_URING_CMD_ADMIN);
+        assert_eq!(u64_at(&sqe, 32), 0x1234);
+        /* Unused fields of SQE are cleared */
+        assert!(sqe[1..4].iter().all(|b| *b == 0));
+        assert!(sqe[40..48].iter().all(|b| *b == 0));
+        assert!(sqe[48 + NVME_URING_CMD_LEN..].iter().all(|b| *b == 0));
+
+        let cmd = &sqe[48..48 + NVME_URING_CMD_LEN];
+        assert_eq!(cmd[0], 0x06);
+        assert_eq!(cmd[1], 0x01);
+        assert_eq!(u32_at(cmd, 4), 0xffff_ffff);
+        assert_eq!(u64_at(cmd, 24), addr);
+        assert_eq!(u32_at(cmd, 36), 4096);
+        assert_eq!(u32_at(cmd, 40), 0x1);
+        assert_eq!(u32_at(cmd, 44), 0x2 << 24);
+        assert_eq!(u32_at(cmd, 64), 5000);
+    }
+
+    #[test]
+    fn test_sqe_encode_io() {
+        let mut sqe = vec![0u8; IO_URING_SQE128_LEN];
+        let mut req = NvmeUringRequest::io(NvmeCmd {
+            opcode:             0x00,
+            nsid:               1,
+            cdw15:              0xabcd,
+            timeout_ms:         1234,
+            ..                  Default::default()
+        }, Vec::new());
+        let opts = NvmeCmdOpts {
+            timeout:            Some(Duration::from_secs(5)),
+            ..                  Default::default()
+        };
+        uring_sqe_encode(&mut sqe, 3, &mut req, &opts, 7);
+        let cmd = &sqe[48..48 + NVME_URING_CMD_LEN];
+
+        assert_eq!(u32_at(&sqe, 8), NVME_URING_CMD_IO);
+        assert_eq!(u64_at(cmd, 24), 0);
+        assert_eq!(u32_at(cmd, 36), 0);
+        assert_eq!(u32_at(cmd, 60), 0xabcd);
+        /* Timeout of command takes precedence over command options */
+        assert_eq!(u32_at(cmd, 64), 1234);
+    }
+
+    #[test]
+    fn test_cqe_decode() {
+        let (user_data, ret) = uring_cqe_decode(&cqe_build(3, 0, 0x55));
+        assert_eq!(user_data, 3);
+        let completion = ret.unwrap();
+        assert!(completion.is_success());
+        assert_eq!(completion.result, 0x55);
+
+        /* DNR, SCT 1 (command specific), SC 09h (invalid log page) */
+        let (user_data, ret) = uring_cqe_decode(
+            &cqe_build(u64::MAX, 0x4109, 0xdead_beef));
+        assert_eq!(user_data, u64::MAX);
+        let completion = ret.unwrap();
+        assert_eq!(completion.status, 0x4109);
+        assert_eq!(completion.sct(), 1);
+        assert_eq!(completion.sc(), 0x09);
+        assert!(completion.dnr());
+        assert_eq!(completion.result, 0xdead_beef);
+        assert!(completion.result_get(true, 0x02).is_err());
+    }
+
+    #[test]
+    fn test_cqe_decode_errno() {
+        let (user_data, ret) = uring_cqe_decode(
+            &cqe_build(1, -libc::EINVAL, 0));
+        assert_eq!(user_data, 1);
+        assert_eq!(ret.unwrap_err(), libc::EINVAL);
+    }
+
+    #[test]
+    fn test_cqe_complete_errno() {
+        let opts = NvmeCmdOptsTable::default();
+        let mut reqs = vec![
+            NvmeUringRequest::io(NvmeCmd {
+                opcode:         0x00,
+                ..              Default::default()
+            }, Vec::new()),
+            NvmeUringRequest::io(NvmeCmd {
+                opcode:         0x00,
+                timeout_ms:     1000,
+                ..              Default::default()
+            }, Vec::new()),
+        ];
+        uring_cqe_complete(&cqe_build(0, -libc::EINVAL, 0), &mut reqs,
+                           &opts).unwrap();
+        uring_cqe_complete(&cqe_build(1, -libc::EINTR, 0), &mut reqs,
+                           &opts).unwrap();
+        match reqs[0].result {
+            Some(Err(NvmeError{kind: ErrorKind::CommandFailed(_)})) => (),
+            ref other => panic!("Expecting CommandFailed, got {:?}", other),
+        }
+        /* Same as ioctl, kernel cancels timed out command with EINTR */
+        match reqs[1].result {
+            Some(Err(NvmeError{kind: ErrorKind::TimedOut(_)})) => (),
+            ref other => panic!("Expecting TimedOut, got {:?}", other),
+        }
+        assert!(uring_cqe_complete(&cqe_build(2, 0, 0), &mut reqs,
+                                   &opts).is_err());
+    }
+
+    /* Complete each submitted command with the next scripted `res` of CQE,
+     * success once the script is exhausted.
+     */
+    struct MockRing {
+        results:                Vec<i32>,
+        submitted:              Vec<u8>,
+    }
+
+    impl UringRing for MockRing {
+        fn run(&mut self, dev_fd: RawFd, reqs: &mut [NvmeUringRequest],
+               opts: &NvmeCmdOptsTable) -> Result<()> {
+            let mut sqe = vec![0u8; IO_URING_SQE128_LEN];
+            for i in 0..reqs.len() {
+                if reqs[i].result.is_some() {
+                    continue;
+                }
+                let cmd_opts = opts.get(reqs[i].is_admin, reqs[i].cmd.opcode);
+                uring_sqe_encode(&mut sqe, dev_fd, &mut reqs[i], &cmd_opts,
+                                 i as u64);
+                self.submitted.push(sqe[SQE_CMD_OFFSET]);
+                let res = if self.results.is_empty() {
+                    0
+                } else {
+                    self.results.remove(0)
+                };
+                uring_cqe_complete(
+                    &cqe_build(u64_at(&sqe, SQE_USER_DATA_OFFSET), res, 0),
+                    reqs, opts)?;
+            }
+            Ok(())
+        }
+
+        fn inflight(&self) -> usize {
+            0
+        }
+    }
+
+    #[test]
+    fn test_batch_run() {
+        let opts = NvmeCmdOptsTable::default();
+        let mut policy = NvmeOpcodePolicy::default();
+        policy.io_allow(0x00);
+        policy.io_allow(0x02);
+        opts.policy_set(policy);
+        opts.set(false, 0x02, NvmeCmdOpts {
+            retries:            2,
+            backoff:            Duration::from_millis(1),
+            ..                  Default::default()
+        });
+        let reqs = vec![
+            /* Write is not allowed by policy */
+            NvmeUringRequest::io(NvmeCmd {
+                opcode:         0x01,
+                nsid:           1,
+                ..              Default::default()
+            }, vec![0u8; 512]),
+            NvmeUringRequest::io(NvmeCmd {
+                opcode:         0x02,
+                nsid:           1,
+                ..              Default::default()
+            }, vec![0u8; 512]),
+            NvmeUringRequest::io(NvmeCmd {
+                opcode:         0x00,
+                nsid:           1,
+                ..              Default::default()
+            }, Vec::new()),
+        ];
+        let mut ring = MockRing {
+            /* Read fails with CRD set, flush fails with DNR set */
+            results:            vec![0x0806, 0x4006],
+            submitted:          Vec::new(),
+        };
+        let reqs = uring_batch_run(&mut ring, 3, &opts, reqs).unwrap();
+
+        /* Only the read is retried */
+        assert_eq!(ring.submitted, vec![0x02, 0x00, 0x02]);
+        match reqs[0].result {
+            Some(Err(NvmeError{kind: ErrorKind::PermissionDenied(_)})) => (),
+            ref other => panic!("Expecting PermissionDenied, got {:?}",
+                                other),
+        }
+        assert!(reqs[1].result.as_ref().unwrap().as_ref().unwrap()
+                .is_success());
+        assert_eq!(reqs[2].result.as_ref().unwrap().as_ref().unwrap().status,
+                   0x4006);
+    }
+}
@@ -17,6 +17,11 @@
  */
 
 use byteorder::{ByteOrder, LittleEndian};
+use std::fs::{read_dir, File};
+use std::io::Read;
+use std::sync::{Mutex, MutexGuard};
+
+use super::error::*;
 
 pub(crate) fn to_u16(i: [u8; 2]) -> u16 {
     LittleEndian::read_u16(&i)
@@ -34,6 +39,16 @@ pub(crate) fn to_u64(i: [u8; 8]) -> u64 {
     LittleEndian::read_u64(&i)
 }
 
+/* Lock `m` even if poisoned. Data behind these locks are always replaced
+ * as a whole, so a panic of other thread never leaves them half updated.
+ */
+pub(crate) fn mutex_lock<T>(m: &Mutex<T>) -> MutexGuard<'_, T> {
+    match m.lock() {
+        Ok(g) => g,
+        Err(e) => e.into_inner(),
+    }
+}
+
 pub(crate) fn bit_field_extract(i: u8, end_include: u8,
                                 start_include: u8) -> u8 {
     (i >> start_include) & ((1 << (end_include - start_include + 1)) - 1)
@@ -69,6 +84,13 @@ pub fn size_bytes_2_size_human(i: u64) -> String {
     format!("{:.2}{}", num, unit)
 }
 
+/* ASCII string of data structure padded by NUL or space */
+pub(crate) fn ascii_padded_to_string(data: &[u8]) -> String {
+    String::from_utf8_lossy(data)
+        .trim_end_matches(['\0', ' '])
+        .to_string()
+}
+
 pub(crate) fn to_hex_string(data: &[u8]) -> String {
     let mut all_zero = true;
     let mut ret = String::new();
@@ -83,3 +105,51 @@ pub(crate) fn to_hex_string(data: &[u8]) -> String {
         false => ret
     }
 }
+
+/* Read sysfs attribute with trailing '\n' removed */
+pub(crate) fn sysfs_read_str(path: &str) -> Result<String> {
+    let mut contents = String::new();
+    File::open(path)?.read_to_string(&mut contents)?;
+    Ok(contents.trim_end().to_string())
+}
+
+/* Entry names of a folder sorted by natural order, e.g. `nvme2` before
+ * `nvme10`. Entries with non UTF-8 name are skipped.
+ */
+pub(crate) fn dir_entry_names_get(path: &str) -> Result<Vec<String>> {
+    let mut ret: Vec<String> = read_dir(path)?
+        .flatten()
+        .filter_map(|e| e.file_name().into_string().ok())
+        .collect();
+    ret.sort_by_key(|n| natural_sort_key(n));
+    Ok(ret)
+}
+
+/* Split name into alternating runs of digits and non-digits, digits runs
+ * are compared by numeric value.
+ */
+fn natural_sort_key(name: &str) -> Vec<(String, u64)> {
+    let mut ret = Vec::new();
+    let mut chunk = String::new();
+    let mut in_digits = false;
+    for c in name.chars() {
+        if c.is_ascii_digit() != in_digits && !chunk.is_empty() {
+            ret.push(natural_sort_chunk(&chunk, in_digits));
+            chunk.clear();
+        }
+        in_digits = c.is_ascii_digit();
+        chunk.push(c);
+    }
+    if !chunk.is_empty() {
+        ret.push(natural_sort_chunk(&chunk, in_digits));
+    }
+    ret
+}
+
+fn natural_sort_chunk(chunk: &str, is_digits: bool) -> (String, u64) {
+    if is_digits {
+        (String::new(), chunk.parse::<u64>().unwrap_or(u64::MAX))
+    } else {
+        (chunk.to_string(), 0)
+    }
+}